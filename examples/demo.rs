@@ -12,9 +12,9 @@ use argh::FromArgs;
 
 use hashlife::{Hashlife, Edge, BoundingBox};
 
-const BLOCK_HALF_UPPER: &'static str = "▀";
-const BLOCK_HALF_LOWER: &'static str = "▄";
-const BLOCK_FULL: &'static str = "█";
+const BLOCK_HALF_UPPER: &str = "▀";
+const BLOCK_HALF_LOWER: &str = "▄";
+const BLOCK_FULL: &str = "█";
 
 /// Hashlife demo
 #[derive(Debug, FromArgs)]
@@ -54,7 +54,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             let viewport_width = grid_size.width;
             let viewport_height = grid_size.height;
 
-            if let None = gol {
+            if gol.is_none() {
                 let width = viewport_width as usize;
                 let height = (viewport_height * 2) as usize;
                 let edge_rules = Edge::Torus;
@@ -106,7 +106,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             .enumerate()
             .for_each(|(i, s)| {
                 let line = Block::default().borders(Borders::NONE).title(s);
-                let mut area = grid_size.clone();
+                let mut area = grid_size;
                 area.y += i as u16;
                 area.height = 2;
                 f.render_widget(line, area);
@@ -114,11 +114,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         }).expect("failed to draw terminal");
         
         match events.next()? {
-            Event::Input(input) => match input {
-                Key::Char('q') => {
-                    break;
-                }
-                _ => {}
+            Event::Input(input) => if let Key::Char('q') = input {
+                break;
             },
             Event::Tick => {
                 if let Some(gol) = &mut gol {
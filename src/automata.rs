@@ -1,3 +1,7 @@
+use std::convert::TryFrom;
+
+use crate::HashlifeError;
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Automata {
@@ -7,23 +11,25 @@ pub enum Automata {
 
 impl Automata {
     pub fn from(number: usize) -> Self {
-        match number {
-            0 => Self::Dead,
-            1 => Self::Alive,
-            _ => panic!("not an automata")
-        }
+        Self::try_from(number).unwrap_or_else(|err| panic!("{}", err))
     }
     pub fn is_dead(&self) -> bool {
-        match self {
-            Automata::Dead => true,
-            _ => false,
-        }
+        matches!(self, Automata::Dead)
     }
 
     pub fn is_alive(&self) -> bool {
-        match self {
-            Automata::Alive => true,
-            _ => false,
+        matches!(self, Automata::Alive)
+    }
+}
+
+impl TryFrom<usize> for Automata {
+    type Error = HashlifeError;
+
+    fn try_from(number: usize) -> Result<Self, Self::Error> {
+        match number {
+            0 => Ok(Self::Dead),
+            1 => Ok(Self::Alive),
+            _ => Err(HashlifeError::InvalidState(number)),
         }
     }
 }
@@ -40,34 +46,6 @@ impl fmt::Display for Automata {
     }
 }
 
-/// Generic simulator for life of any rule set.
-pub fn sim(center: Automata, neighbors: Vec<Automata>, birth: Vec<usize>, servival: Vec<usize>) -> Automata {
-    let living_neighbors: usize = neighbors.iter().map(|a| *a as usize).sum();
-    match center {
-        Automata::Alive => {
-            if servival.contains(&living_neighbors) {
-                Automata::Alive
-            } else {
-                Automata::Dead
-            }
-        },
-        Automata::Dead => {
-            if birth.contains(&living_neighbors) {
-                Automata::Alive
-            } else {
-                Automata::Dead
-            }
-        },
-    }
-}
-
-pub fn simb3s23(center: Automata, n1: Automata, n2: Automata, n3: Automata, n4: Automata, n5: Automata, n6: Automata, n7: Automata, n8: Automata) -> Automata {
-    let automatas = vec![n1, n2, n3, n4, n5, n6, n7, n8];
-    let servival = vec![2, 3];
-    let birth = vec![3];
-    sim(center, automatas, birth, servival)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,15 +78,15 @@ mod tests {
     #[test]
     fn is_alive() {
         let a = Automata::Alive;
-        assert_eq!(a.is_alive(), true);
-        assert_eq!(a.is_dead(), false);
+        assert!(a.is_alive());
+        assert!(!a.is_dead());
     }
 
     #[test]
     fn is_dead() {
         let a = Automata::Dead;
-        assert_eq!(a.is_dead(), true);
-        assert_eq!(a.is_alive(), false);
+        assert!(a.is_dead());
+        assert!(!a.is_alive());
     }
 
     #[test]
@@ -118,6 +96,12 @@ mod tests {
         assert_eq!(a, Automata::Alive);
     }
 
+    #[test]
+    fn try_from_rejects_other_states() {
+        assert_eq!(Automata::try_from(0), Ok(Automata::Dead));
+        assert_eq!(Automata::try_from(2), Err(HashlifeError::InvalidState(2)));
+    }
+
     #[test]
     /// Ensure the Rc returns the same hash as Automata.
     fn cell_rcs_hashed() {
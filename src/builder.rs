@@ -0,0 +1,267 @@
+use std::convert::TryFrom;
+
+use crate::pattern::centred;
+use crate::rle_loader::RleData;
+use crate::{Automata, BoundingBox, CacheLimits, Edge, Hashlife, HashlifeError, Rule};
+
+/// Where the cells of an array or RLE source are placed on the plane.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// The middle of the array is on the origin, the cell on column
+    /// `width / 2` and row `height / 2` from the bottom is at (0, 0). This is
+    /// where `Hashlife::from_array` places the cells.
+    Centered,
+    /// The top left cell of the array is at (0, 0), the rows below it have
+    /// negative `y`.
+    TopLeft,
+}
+
+/// The cells a universe starts with.
+enum Cells {
+    Empty,
+    Array { buffer: Vec<u8>, width: usize, height: usize },
+    Coordinates(Vec<(isize, isize)>),
+}
+
+/// Configures and builds a `Hashlife` universe. By default the universe is
+/// infinite, empty, evolves by Conway's rule from generation 0 and has no
+/// cache limits.
+pub struct HashlifeBuilder {
+    rule: Rule,
+    edge: Edge,
+    origin: Origin,
+    generation: usize,
+    cache_limits: CacheLimits,
+    size: (usize, usize),
+    cells: Cells,
+}
+
+impl HashlifeBuilder {
+    pub fn new() -> Self {
+        Self {
+            rule: Rule::default(),
+            edge: Edge::Infinite,
+            origin: Origin::Centered,
+            generation: 0,
+            cache_limits: CacheLimits::default(),
+            size: (1, 1),
+            cells: Cells::Empty,
+        }
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rule = rule;
+        self
+    }
+
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    /// Where the cells of an array or RLE source are placed. Coordinate lists
+    /// are always on the plane as given.
+    pub fn origin(mut self, origin: Origin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// The number the first generation starts counting from.
+    pub fn generation(mut self, generation: usize) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    pub fn cache_limits(mut self, limits: CacheLimits) -> Self {
+        self.cache_limits = limits;
+        self
+    }
+
+    /// The smallest universe to build, centred on the origin. The universe is
+    /// a square with a power of two side at least as large as the cells,
+    /// which sets the period of a torus and the walls of a truncated
+    /// universe. An infinite universe grows as needed anyway.
+    pub fn size(mut self, width: usize, height: usize) -> Self {
+        self.size = (width, height);
+        self
+    }
+
+    /// Start from an array of states, row by row from the top left.
+    pub fn array(mut self, buffer: Vec<u8>, width: usize, height: usize) -> Self {
+        self.cells = Cells::Array { buffer, width, height };
+        self
+    }
+
+    /// Start from the cells of an RLE file. A file without cells gives an
+    /// empty universe.
+    pub fn rle(mut self, rle: &RleData) -> Self {
+        self.cells = if rle.cells.is_empty() {
+            Cells::Empty
+        } else {
+            let buffer = rle.cells.iter().map(|&cell| cell as u8).collect();
+            Cells::Array { buffer, width: rle.width as usize, height: rle.height as usize }
+        };
+        self
+    }
+
+    /// Start from a list of living cells. A torus wraps the coordinates
+    /// around, a truncated universe fails on a cell outside of it and an
+    /// infinite one on a cell more than 2^61 cells from the origin.
+    pub fn coordinates<I: IntoIterator<Item = (isize, isize)>>(mut self, cells: I) -> Self {
+        self.cells = Cells::Coordinates(cells.into_iter().collect());
+        self
+    }
+
+    /// Start from an empty universe, the default.
+    pub fn empty(mut self) -> Self {
+        self.cells = Cells::Empty;
+        self
+    }
+
+    /// Build the universe, failing when the array does not hold `width *
+    /// height` valid states or when a coordinate is outside of the universe.
+    pub fn build(self) -> Result<Hashlife, HashlifeError> {
+        let mut hashlife = Hashlife::new();
+        hashlife.edge = self.edge;
+        hashlife.set_rule(self.rule);
+
+        let mut top = match &self.cells {
+            Cells::Array { buffer, width, height } => {
+                let expected = width.checked_mul(*height).ok_or(HashlifeError::TooLarge)?;
+                if buffer.len() != expected {
+                    return Err(HashlifeError::BufferSize { expected, actual: buffer.len() });
+                }
+                if expected == 0 {
+                    return Err(HashlifeError::Empty);
+                }
+                for &v in buffer {
+                    Automata::try_from(v as usize)?;
+                }
+                hashlife.construct_from_array(buffer, *width, *height)
+            },
+            Cells::Empty | Cells::Coordinates(_) => hashlife.empty(0),
+        };
+        let (width, height) = self.size;
+        while 1u128 << hashlife.store.get(top).level < width.max(height) as u128 {
+            top = hashlife.expand_empty_border(top);
+        }
+        hashlife.top = Some(top);
+
+        match self.cells {
+            Cells::Array { width, height, .. } if self.origin == Origin::TopLeft => {
+                let (left, top) = centred(width, height);
+                if self.edge == Edge::Truncate {
+                    // The walls stay where they are, so the universe grows
+                    // around the cells before they move.
+                    let area = BoundingBox::from(0, 1 - height as isize, 0, width as isize - 1);
+                    while !BoundingBox::square(hashlife.max_level()).encloses(&area) {
+                        let top = hashlife.expand_empty_border(hashlife.top.unwrap());
+                        hashlife.top = Some(top);
+                    }
+                }
+                hashlife.translate(-left, -top);
+            },
+            Cells::Coordinates(cells) => {
+                for &(x, y) in &cells {
+                    hashlife.check_inside(x, y)?;
+                }
+                for (x, y) in cells {
+                    hashlife.update(x, y, |_| Automata::Alive);
+                }
+            },
+            _ => (),
+        }
+
+        hashlife.gen = self.generation;
+        hashlife.set_cache_limits(self.cache_limits);
+        Ok(hashlife)
+    }
+}
+
+impl Default for HashlifeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rle_loader;
+
+    fn alive(hashlife: &Hashlife) -> Vec<(isize, isize)> {
+        let mut cells = hashlife.live_cells().collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn builds_like_from_array() {
+        let cells = vec![
+            1,1,0,
+            0,1,1,
+        ];
+        let built = HashlifeBuilder::new().edge(Edge::Torus).array(cells.clone(), 3, 2).build().unwrap();
+        let expected = Hashlife::from_array(cells, 3, 2, Edge::Torus);
+        assert_eq!(alive(&built), alive(&expected));
+        assert_eq!(built.max_level(), expected.max_level());
+    }
+
+    #[test]
+    fn top_left_origin_puts_the_first_cell_on_the_origin() {
+        let cells = vec![
+            1,0,0,0,1,
+            0,0,0,0,0,
+            0,0,1,0,0,
+        ];
+        for &edge in &[Edge::Infinite, Edge::Torus, Edge::Truncate] {
+            let hashlife = HashlifeBuilder::new()
+                .edge(edge)
+                .origin(Origin::TopLeft)
+                .array(cells.clone(), 5, 3)
+                .build()
+                .unwrap();
+            let mut expected = vec![(0, 0), (4, 0), (2, -2)];
+            if edge == Edge::Torus {
+                // The 8x8 torus holds column 4 as column -4.
+                expected[1] = (-4, 0);
+            }
+            expected.sort_unstable();
+            assert_eq!(alive(&hashlife), expected, "{:?}", edge);
+        }
+    }
+
+    #[test]
+    fn builds_from_rle_and_coordinates() {
+        let glider = rle_loader::load("x = 3, y = 3\nbo$2bo$3o!");
+        let from_rle = HashlifeBuilder::new().origin(Origin::TopLeft).rle(&glider).build().unwrap();
+        let cells = vec![(1, 0), (2, -1), (0, -2), (1, -2), (2, -2)];
+        let from_coordinates = HashlifeBuilder::new().coordinates(cells.clone()).build().unwrap();
+        assert_eq!(alive(&from_rle), alive(&from_coordinates));
+
+        let truncated = HashlifeBuilder::new().edge(Edge::Truncate).size(4, 4).coordinates(cells);
+        assert_eq!(truncated.build().err(), Some(HashlifeError::OutOfBounds { x: 2, y: -1 }));
+
+        let far = HashlifeBuilder::new().coordinates(vec![(0, 0), (1 << 62, 0)]).build();
+        assert_eq!(far.err(), Some(HashlifeError::OutOfBounds { x: 1 << 62, y: 0 }));
+        let wrapped = HashlifeBuilder::new().edge(Edge::Torus).size(4, 4).coordinates(vec![(1 << 62, 0)]).build().unwrap();
+        assert_eq!(alive(&wrapped), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn sets_generation_size_and_limits() {
+        let limits = CacheLimits { max_nodes: Some(1000), max_bytes: None };
+        let hashlife = HashlifeBuilder::new()
+            .edge(Edge::Torus)
+            .size(100, 20)
+            .generation(42)
+            .cache_limits(limits)
+            .build()
+            .unwrap();
+        assert_eq!(hashlife.get_generation(), 42);
+        assert_eq!(hashlife.get_cache_limits(), limits);
+        assert_eq!(hashlife.max_level(), 7);
+        assert_eq!(hashlife.population(), 0);
+        assert_eq!(HashlifeBuilder::new().array(vec![1, 0], 1, 1).build().err(), Some(HashlifeError::BufferSize { expected: 1, actual: 2 }));
+    }
+}
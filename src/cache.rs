@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use crate::store::Store;
+use crate::{Content, Hashlife, HashlifeError, Node, NodeId};
+
+/// Rough number of bytes used by a canonical node, its arena slot and its
+/// entry in the node table.
+const NODE_BYTES: usize = 2 * size_of::<Node>() + size_of::<NodeId>();
+
+/// Rough number of bytes used by a memoised step or jump result.
+const ENTRY_BYTES: usize = 2 * size_of::<NodeId>();
+
+pub(crate) struct Cache {
+    /// Results of advancing a node by `2^k` generations, a table for each
+    /// exponent `k` so switching between them keeps the results.
+    pub(crate) step: HashMap<usize, HashMap<NodeId, NodeId>>,
+    /// Results of advancing a node by `2^(level-2)` generations.
+    pub(crate) jump: HashMap<NodeId, NodeId>,
+    /// The exponent `step` currently advances by.
+    pub(crate) step_exponent: usize,
+    limits: CacheLimits,
+    last_collection: Option<CollectionStats>,
+}
+
+impl Cache {
+    pub(crate) fn new() -> Self {
+        Self {
+            step: HashMap::new(),
+            jump: HashMap::new(),
+            step_exponent: 0,
+            limits: CacheLimits::default(),
+            last_collection: None,
+        }
+    }
+
+    /// Estimated number of bytes held by the node table and the memo tables.
+    fn memory(&self, store: &Store) -> usize {
+        store.len() * NODE_BYTES + self.entries() * ENTRY_BYTES
+    }
+
+    /// Number of memoised step and jump results, of every exponent.
+    fn entries(&self) -> usize {
+        self.step.values().map(HashMap::len).sum::<usize>() + self.jump.len()
+    }
+
+    pub(crate) fn exceeds(&self, store: &Store) -> bool {
+        let too_many_nodes = self.limits.max_nodes.is_some_and(|max| store.len() > max);
+        let too_many_bytes = self.limits.max_bytes.is_some_and(|max| self.memory(store) > max);
+        too_many_nodes || too_many_bytes
+    }
+}
+
+/// Ceilings on the size of the caches. They are checked after every step and
+/// when one is exceeded the garbage collector runs. If the limit is still
+/// exceeded after collecting, the memoised step results are evicted as well.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheLimits {
+    /// Maximum number of canonical nodes.
+    pub max_nodes: Option<usize>,
+    /// Maximum estimated number of bytes used by nodes and memoised steps.
+    pub max_bytes: Option<usize>,
+}
+
+/// What a garbage collection released.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollectionStats {
+    /// Canonical nodes removed from the node table.
+    pub nodes_freed: usize,
+    /// Canonical nodes kept because they are still reachable.
+    pub nodes_kept: usize,
+    /// Memoised step and jump results removed.
+    pub step_entries_freed: usize,
+    /// Estimated number of bytes released.
+    pub bytes_freed: usize,
+}
+
+/// A generation pinned with `Hashlife::pin`. The nodes of a pinned generation
+/// survive garbage collection until it is unpinned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Snapshot {
+    id: usize,
+    generation: usize,
+}
+
+impl Snapshot {
+    pub fn get_generation(&self) -> usize {
+        self.generation
+    }
+}
+
+impl Hashlife {
+    pub fn set_cache_limits(&mut self, limits: CacheLimits) {
+        self.cache.limits = limits;
+        // A limit even the current tree exceeds fails the next step.
+        let _ = self.enforce_cache_limits();
+    }
+
+    pub fn get_cache_limits(&self) -> CacheLimits {
+        self.cache.limits
+    }
+
+    /// Number of canonical nodes currently held by the node table.
+    pub fn node_count(&self) -> usize {
+        self.store.len()
+    }
+
+    /// Statistics of the most recent garbage collection, automatic or not.
+    pub fn last_collection(&self) -> Option<CollectionStats> {
+        self.cache.last_collection
+    }
+
+    /// Keep the current generation alive through garbage collection.
+    pub fn pin(&mut self) -> Snapshot {
+        let snapshot = Snapshot { id: self.next_pin, generation: self.gen };
+        self.next_pin += 1;
+        if let Some(top) = self.top {
+            self.pinned.insert(snapshot.id, (top, self.gen));
+        }
+        snapshot
+    }
+
+    /// Release a pinned generation, its nodes are freed by the next
+    /// collection unless they are reachable from somewhere else.
+    pub fn unpin(&mut self, snapshot: Snapshot) {
+        self.pinned.remove(&snapshot.id);
+    }
+
+    /// Return the universe to a pinned generation. Returns false if the
+    /// snapshot has been unpinned.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> bool {
+        let (top, gen) = if let Some(pinned) = self.pinned.get(&snapshot.id) {
+            *pinned
+        } else {
+            return false;
+        };
+        self.previous = self.top.take();
+        self.top = Some(top);
+        self.gen = gen;
+        true
+    }
+
+    /// Free every node that is not reachable from the current generation, the
+    /// previous generation or a pinned snapshot. Memoised results are kept
+    /// only when both the node and its result survive.
+    pub fn collect_garbage(&mut self) -> CollectionStats {
+        let mut marked = vec![false; self.store.capacity()];
+        let roots = self.top.iter()
+            .chain(self.previous.iter())
+            .chain(self.pinned.values().map(|(top, _)| top));
+        for root in roots {
+            mark(&self.store, *root, &mut marked);
+        }
+
+        let memory_before = self.cache.memory(&self.store);
+        let entries_before = self.cache.entries();
+        let nodes_freed = self.store.sweep(&marked);
+        let is_marked = |id: &NodeId| marked[id.index()];
+        for steps in self.cache.step.values_mut() {
+            steps.retain(|node, result| is_marked(node) && is_marked(result));
+        }
+        self.cache.jump.retain(|node, result| is_marked(node) && is_marked(result));
+
+        let stats = CollectionStats {
+            nodes_freed,
+            nodes_kept: self.store.len(),
+            step_entries_freed: entries_before - self.cache.entries(),
+            bytes_freed: memory_before - self.cache.memory(&self.store),
+        };
+        self.cache.last_collection = Some(stats);
+        stats
+    }
+
+    /// Collect garbage when a cache limit is exceeded, evicting the memoised
+    /// steps too if collecting alone is not enough. Fails when a limit is
+    /// still exceeded after that.
+    pub(crate) fn enforce_cache_limits(&mut self) -> Result<(), HashlifeError> {
+        if !self.cache.exceeds(&self.store) {
+            return Ok(());
+        }
+        let mut stats = self.collect_garbage();
+        if self.cache.exceeds(&self.store) {
+            let memory_before = self.cache.memory(&self.store);
+            stats.step_entries_freed += self.cache.entries();
+            self.cache.step.clear();
+            self.cache.jump.clear();
+            stats.bytes_freed += memory_before - self.cache.memory(&self.store);
+            self.cache.last_collection = Some(stats);
+        }
+        if self.cache.exceeds(&self.store) {
+            return Err(HashlifeError::CacheLimitExceeded);
+        }
+        Ok(())
+    }
+}
+
+/// Mark every node reachable from the given node.
+fn mark(store: &Store, node: NodeId, marked: &mut [bool]) {
+    if marked[node.index()] {
+        return;
+    }
+    marked[node.index()] = true;
+    if let Content::Branch(c) = store.get(node).content {
+        mark(store, c.nw, marked);
+        mark(store, c.ne, marked);
+        mark(store, c.sw, marked);
+        mark(store, c.se, marked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoundingBox, Edge};
+
+    fn r_pentomino() -> Hashlife {
+        let cells = vec![
+            0,1,1,
+            1,1,0,
+            0,1,0,
+        ];
+        Hashlife::from_array(cells, 3, 3, Edge::Infinite)
+    }
+
+    fn render(hashlife: &mut Hashlife) -> Vec<u8> {
+        let mut buffer = vec![0; 256 * 256];
+        hashlife.draw_to_viewport_buffer(&mut buffer, BoundingBox::from(127, -128, -128, 127));
+        buffer
+    }
+
+    #[test]
+    fn collect_frees_unreachable_nodes() {
+        let mut hashlife = r_pentomino();
+        hashlife.advance(100);
+        let before = hashlife.node_count();
+        let stats = hashlife.collect_garbage();
+        assert!(stats.nodes_freed > 0);
+        assert!(stats.bytes_freed > 0);
+        assert_eq!(stats.nodes_kept, hashlife.node_count());
+        assert_eq!(before, stats.nodes_freed + stats.nodes_kept);
+        assert_eq!(hashlife.last_collection(), Some(stats));
+
+        // Collection does not change the simulation.
+        let mut reference = r_pentomino();
+        hashlife.advance(50);
+        reference.advance(150);
+        assert_eq!(render(&mut hashlife), render(&mut reference));
+    }
+
+    #[test]
+    fn pinned_snapshot_survives_collection() {
+        let mut hashlife = r_pentomino();
+        hashlife.advance(10);
+        let snapshot = hashlife.pin();
+        let expected = render(&mut hashlife);
+        hashlife.advance(100);
+        hashlife.collect_garbage();
+        assert!(hashlife.restore(&snapshot));
+        assert_eq!(hashlife.get_generation(), 10);
+        assert_eq!(snapshot.get_generation(), 10);
+        assert_eq!(render(&mut hashlife), expected);
+
+        hashlife.unpin(snapshot);
+        assert!(!hashlife.restore(&snapshot));
+    }
+
+    #[test]
+    fn node_limit_triggers_collection() {
+        let mut hashlife = r_pentomino();
+        hashlife.set_cache_limits(CacheLimits { max_nodes: Some(500), max_bytes: None });
+        for _ in 0..200 {
+            hashlife.next_generation();
+        }
+        assert!(hashlife.last_collection().is_some());
+        let mut reference = r_pentomino();
+        reference.advance(200);
+        assert!(hashlife.node_count() < reference.node_count());
+        assert_eq!(render(&mut hashlife), render(&mut reference));
+    }
+}
@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::ParseRuleError;
+
+/// Error returned by the fallible entry points of the crate, the `try_`
+/// variants of the methods that panic on bad input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HashlifeError {
+    /// A cell state other than 0 (dead) or 1 (alive).
+    InvalidState(usize),
+    /// A buffer that does not hold `width * height` cells.
+    BufferSize { expected: usize, actual: usize },
+    /// A universe or pattern without a single cell.
+    Empty,
+    /// A malformed pattern file, `line` and `column` count from 1.
+    Parse { line: usize, column: usize, reason: String },
+    /// A malformed rule.
+    Rule(ParseRuleError),
+    /// A cell outside of a truncated universe, or too far from the origin
+    /// for an infinite one.
+    OutOfBounds { x: isize, y: isize },
+    /// A rectangle whose top is below its bottom or whose right is left of
+    /// its left.
+    InvalidRectangle,
+    /// More cells than can be held in memory.
+    TooLarge,
+    /// The cache limits are still exceeded after collecting garbage and
+    /// evicting the memoised steps.
+    CacheLimitExceeded,
+    /// The generation count does not fit in a `usize`.
+    GenerationOverflow,
+    /// A generation before the current one.
+    GenerationInPast { current: usize, requested: usize },
+    /// A file that cannot be read.
+    Io { kind: io::ErrorKind, message: String },
+}
+
+impl HashlifeError {
+    pub(crate) fn parse(line: usize, column: usize, reason: &str) -> Self {
+        HashlifeError::Parse { line, column, reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for HashlifeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashlifeError::InvalidState(state) => write!(f, "{} is not a cell state", state),
+            HashlifeError::BufferSize { expected, actual } => {
+                write!(f, "expected a buffer of {} cells, got {}", expected, actual)
+            },
+            HashlifeError::Empty => write!(f, "a universe needs at least one cell"),
+            HashlifeError::Parse { line, column, reason } => write!(f, "line {}, column {}: {}", line, column, reason),
+            HashlifeError::Rule(err) => err.fmt(f),
+            HashlifeError::OutOfBounds { x, y } => write!(f, "cell ({}, {}) is outside of the universe", x, y),
+            HashlifeError::InvalidRectangle => write!(f, "the rectangle is inside out"),
+            HashlifeError::TooLarge => write!(f, "too many cells to hold in memory"),
+            HashlifeError::CacheLimitExceeded => write!(f, "the cache limits cannot be met"),
+            HashlifeError::GenerationOverflow => write!(f, "the generation count overflows"),
+            HashlifeError::GenerationInPast { current, requested } => {
+                write!(f, "cannot go back from generation {} to {}", current, requested)
+            },
+            HashlifeError::Io { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for HashlifeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HashlifeError::Rule(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseRuleError> for HashlifeError {
+    fn from(err: ParseRuleError) -> Self {
+        HashlifeError::Rule(err)
+    }
+}
+
+impl From<io::Error> for HashlifeError {
+    fn from(err: io::Error) -> Self {
+        HashlifeError::Io { kind: err.kind(), message: err.to_string() }
+    }
+}
@@ -0,0 +1,203 @@
+//! Pattern files in any of the supported formats, recognised by content.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::{life105, life106, macrocell, plaintext, rle};
+use crate::{BoundingBox, Hashlife, HashlifeError, Metadata, Pattern};
+
+/// Coordinates beyond this are refused when reading, so every cell of a
+/// pattern is far from overflowing.
+pub(crate) const MAX_SIDE: usize = 1 << 60;
+
+/// The pattern file formats that can be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    Rle,
+    Macrocell,
+    /// `.cells` files.
+    Plaintext,
+    Life105,
+    Life106,
+}
+
+impl Format {
+    /// Recognise the format of a pattern file from its first lines.
+    pub fn detect(source: &str) -> Option<Format> {
+        let mut lines = source.lines().map(str::trim).filter(|line| !line.is_empty());
+        let first = lines.clone().next()?;
+        if first.starts_with("[M2]") {
+            return Some(Format::Macrocell);
+        }
+        if first.starts_with("#Life 1.05") {
+            return Some(Format::Life105);
+        }
+        if first.starts_with("#Life 1.06") {
+            return Some(Format::Life106);
+        }
+        if first.starts_with('!') {
+            return Some(Format::Plaintext);
+        }
+        let first = lines.find(|line| !line.starts_with('#'))?;
+        if first.starts_with('x') && first.contains('=') {
+            Some(Format::Rle)
+        } else if first.chars().all(|c| c == '.' || c == 'O') {
+            Some(Format::Plaintext)
+        } else if first.split_whitespace().filter(|v| v.parse::<isize>().is_ok()).count() == 2 {
+            Some(Format::Life106)
+        } else {
+            None
+        }
+    }
+}
+
+/// Read a pattern file in any of the known formats.
+pub fn read_pattern(source: &str) -> Result<Pattern, HashlifeError> {
+    match Format::detect(source) {
+        Some(Format::Rle) => rle::read(source),
+        Some(Format::Macrocell) => {
+            let mut hashlife = macrocell::read(source)?;
+            let mut pattern = match hashlife.live_bounds() {
+                Some(bounds) => hashlife.extract(&bounds),
+                None => Pattern::from_cells(0, 0, 0, 0, std::iter::empty()),
+            };
+            pattern.metadata_mut().rule = Some(hashlife.get_rule().clone());
+            Ok(pattern)
+        },
+        Some(Format::Plaintext) => plaintext::read(source),
+        Some(Format::Life105) => life105::read(source),
+        Some(Format::Life106) => life106::read(source),
+        None => Err(HashlifeError::parse(1, 1, "not a known pattern format")),
+    }
+}
+
+/// Read a pattern file in any of the known formats, see `read_pattern`.
+pub fn load_pattern<P: AsRef<Path>>(path: P) -> Result<Pattern, HashlifeError> {
+    read_pattern(&fs::read_to_string(path)?)
+}
+
+/// The rectangle a writer writes: the given one, or the smallest one holding
+/// every living cell. `None` when there is no living cell to write.
+///
+/// Fails on a rectangle whose width or height does not fit in an `isize`,
+/// or reaching the lowest `y`, as the rows down are the negated `y`.
+pub(crate) fn written_area(hashlife: &Hashlife, area: Option<&BoundingBox>) -> io::Result<Option<BoundingBox>> {
+    let fits = |low: isize, high: isize| high.checked_sub(low).and_then(|side| side.checked_add(1)).is_some();
+    match area {
+        Some(area) if area.top() < area.bottom() || area.right() < area.left() => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, HashlifeError::InvalidRectangle))
+        },
+        Some(area) if !fits(area.left(), area.right()) || !fits(area.bottom(), area.top()) || area.bottom() == isize::MIN => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, HashlifeError::TooLarge))
+        },
+        Some(area) => Ok(Some(*area)),
+        None => Ok(hashlife.live_bounds()),
+    }
+}
+
+/// The living cells inside a rectangle as rows down and columns across the
+/// plane, row by row from the top.
+pub(crate) fn rows_down(hashlife: &Hashlife, area: &BoundingBox) -> Vec<(isize, isize)> {
+    let mut cells = Vec::new();
+    hashlife.for_each_live_in(area, |x, y| cells.push((-y, x)));
+    cells.sort_unstable();
+    cells
+}
+
+/// A pattern holding the cells at the given columns across and rows down the
+/// plane, bounded by them.
+pub(crate) fn pattern_at(cells: &[(isize, isize)], metadata: Metadata) -> Pattern {
+    let left = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+    let right = cells.iter().map(|&(x, _)| x).max().unwrap_or(-1);
+    let top = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+    let bottom = cells.iter().map(|&(_, y)| y).max().unwrap_or(-1);
+    let relative = cells.iter().map(|&(x, y)| ((x - left) as usize, (y - top) as usize));
+    let mut pattern = Pattern::from_cells((right - left + 1) as usize, (bottom - top + 1) as usize, left, -top, relative);
+    *pattern.metadata_mut() = metadata;
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Automata;
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(Format::detect("#N Glider\nx = 3, y = 3\nbo$2bo$3o!"), Some(Format::Rle));
+        assert_eq!(Format::detect("[M2] (golly 4.2)\n#R B3/S23\n.*$"), Some(Format::Macrocell));
+        assert_eq!(Format::detect("!Name: Glider\n.O.\n..O\nOOO"), Some(Format::Plaintext));
+        assert_eq!(Format::detect("\n.O\nOO"), Some(Format::Plaintext));
+        assert_eq!(Format::detect("#Life 1.05\n#P -1 -1\n.*"), Some(Format::Life105));
+        assert_eq!(Format::detect("#Life 1.06\n0 -1\n"), Some(Format::Life106));
+        assert_eq!(Format::detect("0 -1\n1 0\n"), Some(Format::Life106));
+        assert_eq!(Format::detect("hello"), None);
+        assert_eq!(Format::detect(""), None);
+    }
+
+    #[test]
+    fn every_format_reads_the_same_glider() {
+        let sources = [
+            "x = 3, y = 3\nbo$2bo$3o!",
+            "[M2]\n.*$..*$***$",
+            "!Name: Glider\n.O.\n..O\nOOO\n",
+            "#Life 1.05\n#P 0 0\n.*\n..*\n***\n",
+            "#Life 1.06\n1 0\n2 1\n0 2\n1 2\n2 2\n",
+        ];
+        for source in &sources {
+            let pattern = read_pattern(source).unwrap();
+            let (left, top) = (pattern.bounds().left(), pattern.bounds().top());
+            let mut cells = Vec::new();
+            for row in 0..3 {
+                for col in 0..3 {
+                    if pattern.get(left + col, top - row) == Automata::Alive {
+                        cells.push((col, row));
+                    }
+                }
+            }
+            assert_eq!(cells, vec![(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)], "{}", source);
+            assert_eq!(pattern.height(), 3, "{}", source);
+        }
+    }
+
+    #[test]
+    fn writers_refuse_areas_that_overflow() {
+        let mut hashlife = Hashlife::from_array(vec![0; 16], 4, 4, crate::Edge::Torus);
+        hashlife.set(1, 0, crate::Automata::Alive);
+        let options = rle::WriteOptions::default();
+        let areas = [
+            BoundingBox::from(isize::MAX, isize::MIN, isize::MIN, isize::MAX),
+            BoundingBox::from(0, 0, -1, isize::MAX),
+            BoundingBox::from(0, isize::MIN, 0, 0),
+        ];
+        for area in &areas {
+            let mut out = Vec::new();
+            let results = [
+                rle::write(&hashlife, Some(area), &mut out, &options),
+                plaintext::write(&hashlife, Some(area), &mut out, &options),
+                life105::write(&hashlife, Some(area), &mut out, &options),
+                life106::write(&hashlife, Some(area), &mut out, &options),
+            ];
+            for result in &results {
+                assert_eq!(result.as_ref().map_err(io::Error::kind), Err(io::ErrorKind::InvalidInput), "{:?}", area);
+            }
+        }
+        // The widest rectangle that fits, on a row without a living cell.
+        let mut out = Vec::new();
+        life106::write(&hashlife, Some(&BoundingBox::from(1, 1, -(1 << 62), (1 << 62) - 2)), &mut out, &options).unwrap();
+        assert_eq!(out, b"#Life 1.06\n");
+    }
+
+    #[test]
+    fn load_pattern_reads_files() {
+        let path = std::env::temp_dir().join(format!("hashlife-{}.cells", std::process::id()));
+        fs::write(&path, "!Name: Blinker\nOOO\n").unwrap();
+        let pattern = load_pattern(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(pattern.metadata().name.as_deref(), Some("Blinker"));
+        assert_eq!((pattern.width(), pattern.height()), (3, 1));
+        let missing = load_pattern(&path);
+        assert!(matches!(missing, Err(HashlifeError::Io { kind: io::ErrorKind::NotFound, .. })));
+    }
+}
@@ -1,12 +1,41 @@
 mod automata;
+mod builder;
+mod cache;
+mod error;
+mod format;
+#[cfg(feature = "parallel")]
+mod parallel;
+pub mod life105;
+pub mod life106;
+pub mod macrocell;
+mod pattern;
+pub mod plaintext;
+mod query;
+pub mod rle;
+mod rule;
+mod sparse;
+mod step;
+mod store;
+mod transform;
 pub mod rle_loader;
 
 pub use automata::Automata;
+pub use builder::{HashlifeBuilder, Origin};
+pub use cache::{CacheLimits, CollectionStats, Snapshot};
+pub use error::HashlifeError;
+pub use format::{load_pattern, read_pattern, Format};
+pub use pattern::{CombineMode, Metadata, Pattern};
+pub use query::LiveCells;
+pub use rule::{ParseRuleError, Rule};
+pub use transform::Transform;
+
+use cache::Cache;
+use rule::Transitions;
+#[cfg(not(feature = "parallel"))]
+use step::Serial;
+use store::{Nodes, Store, LEAF_LEVEL, MAX_LEVEL};
 
-use std::rc::Rc;
 use std::collections::HashMap;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 
 /// A `Node` represents the top of a tree (or subtree) in the Hashlife data
 /// structure. The state of Hashlife is stored in a `Node` and its children
@@ -14,143 +43,109 @@ use std::hash::{Hash, Hasher};
 /// 
 /// # Level
 /// 
-/// Each `Node` has a `level` where `0` is a leaf and
-/// any positive number is a branch. Given a level 'x', it can be derived
-/// that the node is `x` nodes away from the leaf level. All leaves are on
-/// the same level.
+/// Each `Node` has a `level`, it covers a square of `2^level` cells. Nodes
+/// up to level 3 are leaves holding their cells in a bitboard, any higher
+/// level is a branch. All leaves below the top of a tree are on level 3,
+/// only universes smaller than 8x8 have smaller leaves.
 /// 
 /// # Population
 /// 
 /// Each node has a `population` informing how many living `Automata::Alive`
-/// leaves this subtree constains. If the `Node` is a leaf, then the
-/// popilation can only be 1 or 0 respectively representing
-/// `Automata::Alive` or `Automata::Dead`.
+/// cells this subtree constains. Repeated subtrees let a small tree hold more
+/// cells than a `usize` can count, so the population is a `u128`.
 /// 
-/// # Children
+/// # Content
 /// 
 /// The `Node` in a hashlife algorithm is known as a QuadTree where the node
-/// points to four child nodes. The children field is an optional and is
-/// `None` only when it is a leaf node at `level=0`.
-#[derive(Debug)]
+/// points to four child nodes. Nodes live in the node arena of a `Hashlife`
+/// and the children of a branch are stored inline as `NodeId` handles into
+/// that arena. A leaf stores its cells in a `u64` instead, one bit per cell
+/// and eight bits per row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Node {
     level: usize,
-    population: usize,
-    hash: u64,
-    children: Option<Children>,
+    population: u128,
+    content: Content,
 }
 
-impl PartialEq for Node {
-    fn eq(&self, other: &Node) -> bool {
-        self.level == other.level && self.population == other.population && self.hash == other.hash
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Content {
+    Leaf(u64),
+    Branch(Children),
 }
 
-impl Eq for Node {}
+/// Handle on a `Node` stored in the node arena.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct NodeId(u32);
+
+impl NodeId {
+    /// The dead single cell leaf, present in every arena.
+    const DEAD: NodeId = NodeId(0);
+    /// The alive single cell leaf, present in every arena.
+    const ALIVE: NodeId = NodeId(1);
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
 
-#[derive(Hash, Debug)]
+/// The four quadrants of a branch. Nodes are canonical, so two children are
+/// equal only if they refer to the very same nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Children {
-    nw: Rc<Node>,
-    ne: Rc<Node>,
-    sw: Rc<Node>,
-    se: Rc<Node>,
+    nw: NodeId,
+    ne: NodeId,
+    sw: NodeId,
+    se: NodeId,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Edge {
     Torus,
     Truncate,
     Infinite,
 }
 
+/// The grandchildren of a node that touch its centre or the middle of an
+/// edge. The four corner grandchildren are only ever reached through the
+/// children.
 struct GrandChildren {
-    nwnw: Rc<Node>,
-    nwne: Rc<Node>,
-    nwsw: Rc<Node>,
-    nwse: Rc<Node>,
-    nenw: Rc<Node>,
-    nene: Rc<Node>,
-    nesw: Rc<Node>,
-    nese: Rc<Node>,
-    swnw: Rc<Node>,
-    swne: Rc<Node>,
-    swsw: Rc<Node>,
-    swse: Rc<Node>,
-    senw: Rc<Node>,
-    sene: Rc<Node>,
-    sesw: Rc<Node>,
-    sese: Rc<Node>,
-}
-
-struct GrandAutomata {
-    nwnw: Automata,
-    nwne: Automata,
-    nwsw: Automata,
-    nwse: Automata,
-    nenw: Automata,
-    nene: Automata,
-    nesw: Automata,
-    nese: Automata,
-    swnw: Automata,
-    swne: Automata,
-    swsw: Automata,
-    swse: Automata,
-    senw: Automata,
-    sene: Automata,
-    sesw: Automata,
-    sese: Automata,
-}
-
-/// A nonant is a 1/9 separation of a space. This structure represents a node
-/// that is separated into 9 nonants.
-/// 
-/// Invarient: The node that constructs this nonant collection must have
-/// `level>=3`.
-struct Nonants {
-    nw: Rc<Node>,
-    ne: Rc<Node>,
-    sw: Rc<Node>,
-    se: Rc<Node>,
-    n_: Rc<Node>,
-    e_: Rc<Node>,
-    s_: Rc<Node>,
-    w_: Rc<Node>,
-    c_: Rc<Node>,
+    nwne: NodeId,
+    nwsw: NodeId,
+    nwse: NodeId,
+    nenw: NodeId,
+    nesw: NodeId,
+    nese: NodeId,
+    swnw: NodeId,
+    swne: NodeId,
+    swse: NodeId,
+    senw: NodeId,
+    sene: NodeId,
+    sesw: NodeId,
 }
 
 pub struct Hashlife {
+    store: Store,
     cache: Cache,
     edge: Edge,
-    top: Option<Rc<Node>>,
-    previous: Option<Rc<Node>>,
+    rule: Rule,
+    transitions: Transitions,
+    top: Option<NodeId>,
+    previous: Option<NodeId>,
     gen: usize,
-}
-
-struct Cache {
-    join: HashMap<u64, Rc<Node>>,
-    step: HashMap<Rc<Node>, Rc<Node>>,
-    dead: Option<Rc<Node>>,
-    alive: Option<Rc<Node>>,
-}
-
-impl Cache {
-    fn new() -> Self {
-        Self {
-            join: HashMap::new(),
-            step: HashMap::new(),
-            dead: None,
-            alive: None,
-        }
-    }
+    step_exponent: usize,
+    pinned: HashMap<usize, (NodeId, usize)>,
+    next_pin: usize,
 }
 
 struct ConstructionParameters<'a> {
-    level: usize,
-    vector: &'a Vec<u8>,
+    vector: &'a [u8],
     width: usize,
     height: usize,
     bound: BoundingBox,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BoundingBox {
     top: isize,
     bottom: isize,
@@ -177,6 +172,21 @@ impl BoundingBox {
         Self { top, bottom, left, right }
     }
 
+    /// The square covered by a top node of the given level.
+    fn square(level: usize) -> Self {
+        let side = 1isize << level;
+        let (left, top) = top_corner(level);
+        Self::from(top, top - side + 1, left, left + side - 1)
+    }
+
+    fn contains(&self, x: isize, y: isize) -> bool {
+        self.left <= x && x <= self.right && self.bottom <= y && y <= self.top
+    }
+
+    fn encloses(&self, other: &BoundingBox) -> bool {
+        self.contains(other.left, other.top) && self.contains(other.right, other.bottom)
+    }
+
     fn collides(&self, other: &BoundingBox) -> bool {
         // up is -y, down is +y
         let other_below_self = other.top < self.bottom;
@@ -184,16 +194,35 @@ impl BoundingBox {
         // left is -x, right is +x
         let other_right_of_self = other.left > self.right;
         let other_left_of_self = other.right < self.left;
-        return !(other_below_self || other_above_self || other_right_of_self || other_left_of_self);
+        !(other_below_self || other_above_self || other_right_of_self || other_left_of_self)
         // !(other.top < self.bottom || other.bottom > self.top || other.left > self.right || other.right < self.left)
     }
 
-    fn width(&self) -> usize {
-        (self.right - self.left + 1) as usize
+    /// The difference is taken as unsigned so it cannot overflow, only a
+    /// rectangle spanning the whole plane wraps around to zero.
+    pub fn width(&self) -> usize {
+        (self.right.wrapping_sub(self.left) as usize).wrapping_add(1)
+    }
+
+    /// Like `width`.
+    pub fn height(&self) -> usize {
+        (self.top.wrapping_sub(self.bottom) as usize).wrapping_add(1)
+    }
+
+    pub fn top(&self) -> isize {
+        self.top
+    }
+
+    pub fn bottom(&self) -> isize {
+        self.bottom
+    }
+
+    pub fn left(&self) -> isize {
+        self.left
     }
 
-    fn height(&self) -> usize {
-        (self.top - self.bottom + 1) as usize
+    pub fn right(&self) -> isize {
+        self.right
     }
 
     fn index(&self, x: isize, y: isize) -> usize {
@@ -209,235 +238,267 @@ impl BoundingBox {
 impl Hashlife {
     fn new() -> Self {
         Self {
+            store: Store::new(),
             cache: Cache::new(),
             edge: Edge::Infinite,
+            rule: Rule::default(),
+            transitions: Transitions::new(&Rule::default()),
             top: None,
             previous: None,
             gen: 0,
+            step_exponent: 0,
+            pinned: HashMap::new(),
+            next_pin: 0,
         }
     }
 
-    fn join(&mut self, nw: Rc<Node>, ne: Rc<Node>, sw: Rc<Node>, se: Rc<Node>) -> Rc<Node> {
-        let children = Children::from(&nw, &ne, &sw, &se);
-        let level = nw.level + 1;
-        assert_eq!(nw.level, ne.level);
-        assert_eq!(nw.level, sw.level);
-        assert_eq!(nw.level, se.level);
-        let population = nw.population + ne.population + sw.population + se.population;
-        let hash = calculate_hash(&children);
-        if let Some(ref_to_node) = self.cache.join.get(&hash) {
-            return Rc::clone(ref_to_node);
-        }
-        let children = Some(children);
-        let node = Node {
-            level,
-            population,
-            hash,
-            children
-        };
-        let node = Rc::new(node);
-        self.cache.join.insert(hash, Rc::clone(&node));
-        return node;
-    }
-
-    /// Separates a node into 9 
-    fn into_nonants(&mut self, node: Rc<Node>) -> Nonants {
-        match &node.level {
-            0 => panic!("attempted to bread node into 9x9 at level 0"),
-            1 => panic!("attempted to bread node into 9x9 at level 1"),
-            2 => panic!("attempted to bread node into 9x9 at level 2"),
-            _ => ()
-        };
+    fn join(&mut self, nw: NodeId, ne: NodeId, sw: NodeId, se: NodeId) -> NodeId {
+        self.store.join(Children { nw, ne, sw, se })
+    }
 
-        let c = node.get_children();
-        let g = node.get_grand_children();
-
-        Nonants {
-            nw: Rc::clone(&c.nw),
-            ne: Rc::clone(&c.ne),
-            sw: Rc::clone(&c.sw),
-            se: Rc::clone(&c.se),
-            n_: self.join(g.nwne, g.nenw, Rc::clone(&g.nwse), Rc::clone(&g.nesw)),
-            e_: self.join(Rc::clone(&g.nesw), g.nese, Rc::clone(&g.senw), g.sene),
-            s_: self.join(Rc::clone(&g.swne), Rc::clone(&g.senw), g.swse, g.sesw),
-            w_: self.join(g.nwsw, Rc::clone(&g.nwse), g.swnw, Rc::clone(&g.swne)),
-            c_: self.join(g.nwse, g.nesw, g.swne, g.senw),
-        }
+    /// Advance the centre of a node by `2^k` generations, where `k` is the
+    /// exponent the step cache is currently set to. With the `parallel`
+    /// feature the work is spread over all cores.
+    fn step(&mut self, node: NodeId) -> NodeId {
+        #[cfg(feature = "parallel")]
+        let result = parallel::step(&mut self.store, &mut self.cache, &self.transitions, node);
+        #[cfg(not(feature = "parallel"))]
+        let result = step::step(&mut Serial::new(&mut self.store, &mut self.cache, &self.transitions), node);
+        result
     }
 
-    fn join_nonants(&mut self, nodes: Nonants) -> Rc<Node> {
-        let nw_res = self.join(
-            Rc::clone(&nodes.nw.get_children().se),
-            Rc::clone(&nodes.n_.get_children().sw),
-            Rc::clone(&nodes.w_.get_children().ne),
-            Rc::clone(&nodes.c_.get_children().nw)
-        );
-        let ne_res = self.join(
-            Rc::clone(&nodes.n_.get_children().se),
-            Rc::clone(&nodes.ne.get_children().sw),
-            Rc::clone(&nodes.c_.get_children().ne),
-            Rc::clone(&nodes.e_.get_children().nw)
-        );
-        let sw_res = self.join(
-            Rc::clone(&nodes.w_.get_children().se),
-            Rc::clone(&nodes.c_.get_children().sw),
-            Rc::clone(&nodes.sw.get_children().ne),
-            Rc::clone(&nodes.s_.get_children().nw)
-        );
-        let se_res = self.join(
-            Rc::clone(&nodes.c_.get_children().se),
-            Rc::clone(&nodes.e_.get_children().sw),
-            Rc::clone(&nodes.s_.get_children().ne),
-            Rc::clone(&nodes.se.get_children().nw),
-        );
-        self.join(nw_res, ne_res, sw_res, se_res)
+    /// Select the exponent used by `step`. The results of each exponent are
+    /// memoised in a table of their own, so none is lost by switching.
+    fn use_step_exponent(&mut self, k: usize) {
+        self.cache.step_exponent = k;
     }
 
-    /// Invarient: Node.level >= 2
-    fn step(&mut self, node: Rc<Node>) -> Rc<Node> {
-        if let Some(ref_to_node) = self.cache.step.get(&node) {
-            return Rc::clone(ref_to_node);
+    fn expand_empty_border(&mut self, node: NodeId) -> NodeId {
+        if self.store.get(node).level == 0 {
+            // A single cell is the north east quadrant around the origin.
+            return self.join(NodeId::DEAD, node, NodeId::DEAD, NodeId::DEAD);
         }
-        let step = match &node.level {
-            0 => panic!("attempted to step a node with level 0"),
-            1 => panic!("attempted to step a node with level 1"),
-            2 => {
-                let g = node.get_grand_automata();
-                let nw = automata::simb3s23(g.nwse, g.nwnw, g.nwne, g.nenw, g.nesw, g.senw, g.swne, g.swnw, g.nwsw);
-                let ne = automata::simb3s23(g.nesw, g.nwne, g.nenw, g.nene, g.nese, g.sene, g.senw, g.swne, g.nwse);
-                let sw = automata::simb3s23(g.swne, g.nwsw, g.nwse, g.nesw, g.senw, g.sesw, g.swse, g.swsw, g.swnw);
-                let se = automata::simb3s23(g.senw, g.nwse, g.nesw, g.nese, g.sene, g.sese, g.sesw, g.swse, g.swne);
-                let nw = self.make_automata(nw);
-                let ne = self.make_automata(ne);
-                let sw = self.make_automata(sw);
-                let se = self.make_automata(se);
-                self.join(nw, ne, sw, se)
-            },
-            _ => {
+        let c = self.store.split(node);
+        let e = self.empty(self.store.get(node).level - 1);
+        let nw = self.join(e, e, e, c.nw);
+        let ne = self.join(e, e, c.ne, e);
+        let sw = self.join(e, c.sw, e, e);
+        let se = self.join(c.se, e, e, e);
+        self.join(nw, ne, sw, se)
+    }
 
-                let mut g9x9 = self.into_nonants(Rc::clone(&node));
+    /// Change the rule the universe evolves by. Memoised results were computed
+    /// with the previous rule, so they are discarded.
+    pub fn set_rule(&mut self, rule: Rule) {
+        if self.rule != rule {
+            self.transitions = Transitions::new(&rule);
+            self.rule = rule;
+            self.cache.step.clear();
+            self.cache.jump.clear();
+        }
+    }
 
-                g9x9.nw = self.step(g9x9.nw);
-                g9x9.ne = self.step(g9x9.ne);
-                g9x9.sw = self.step(g9x9.sw);
-                g9x9.se = self.step(g9x9.se);
-                g9x9.n_ = self.step(g9x9.n_);
-                g9x9.e_ = self.step(g9x9.e_);
-                g9x9.s_ = self.step(g9x9.s_);
-                g9x9.w_ = self.step(g9x9.w_);
-                g9x9.c_ = self.step(g9x9.c_);
+    pub fn get_rule(&self) -> &Rule {
+        &self.rule
+    }
 
-                self.join_nonants(g9x9)
-            },
-        };
-        self.cache.step.insert(node, Rc::clone(&step));
-        step
-    }
-
-    fn expand_empty_border(&mut self, node: Rc<Node>) -> Rc<Node> {
-        let c = node.get_children();
-        let e = self.empty(node.level-1);
-        let e = || Rc::clone(&e);
-        let nw = self.join(e(), e(), e(), Rc::clone(&c.nw));
-        let ne = self.join(e(), e(), Rc::clone(&c.ne), e());
-        let sw = self.join(e(), Rc::clone(&c.sw), e(), e());
-        let se = self.join(Rc::clone(&c.se), e(), e(), e());
-        self.join(nw, ne, sw, se)
+    /// Set the number of generations `next_generation` advances by to `2^k`.
+    pub fn set_step_exponent(&mut self, k: usize) {
+        self.step_exponent = k;
+    }
+
+    pub fn get_step_exponent(&self) -> usize {
+        self.step_exponent
     }
 
+    /// Advance the universe by `2^step_exponent` generations (a single
+    /// generation unless changed with `set_step_exponent`).
     pub fn next_generation(&mut self) {
-        let top = if let Some(top) = &self.top {
-            Rc::clone(top)
-        } else {
-            return;
+        self.step_pow2(self.step_exponent);
+    }
+
+    /// Advance the universe by `2^k` generations in a single call.
+    pub fn step_pow2(&mut self, k: usize) {
+        self.try_step_pow2(k).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `step_pow2`, but fails when the generation count would overflow
+    /// or the cache limits cannot be met, see `try_advance`.
+    pub fn try_step_pow2(&mut self, k: usize) -> Result<(), HashlifeError> {
+        if k >= usize::BITS as usize {
+            return Err(HashlifeError::GenerationOverflow);
+        }
+        self.try_advance(1 << k)
+    }
+
+    /// Advance the universe by exactly `generations` generations. The count is
+    /// broken down into power of two jumps, one for each bit that is set.
+    ///
+    /// Panics when the generation count overflows or the cache limits cannot
+    /// be met, see `try_advance`.
+    pub fn advance(&mut self, generations: usize) {
+        self.try_advance(generations).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `advance`, but fails when the generation count would overflow or
+    /// the cache limits are still exceeded after collecting garbage and
+    /// evicting the memoised steps. The limits are checked after every step,
+    /// and the first one that cannot meet them stops the advance. The cells
+    /// are left as they were on failure, and become the previous generation
+    /// the next diff is drawn against.
+    pub fn try_advance(&mut self, generations: usize) -> Result<(), HashlifeError> {
+        let gen = self.gen.checked_add(generations).ok_or(HashlifeError::GenerationOverflow)?;
+        let top = match self.top {
+            Some(top) => top,
+            None => return Ok(()),
         };
-        self.previous = Some(Rc::clone(&top));
-        let next = match self.edge {
+        // The old top node is the previous one, so it survives collection
+        // and can be put back on failure.
+        self.previous = Some(top);
+        let mut remaining = generations;
+        let mut k = 0;
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                if let Err(err) = self.advance_pow2(k) {
+                    self.top = Some(top);
+                    return Err(err);
+                }
+            }
+            remaining >>= 1;
+            k += 1;
+        }
+        self.gen = gen;
+        Ok(())
+    }
+
+    /// Advance the universe until it reaches the given generation.
+    pub fn goto_generation(&mut self, generation: usize) {
+        self.try_goto_generation(generation).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `goto_generation`, but fails instead of panicking, see
+    /// `try_advance`.
+    pub fn try_goto_generation(&mut self, generation: usize) -> Result<(), HashlifeError> {
+        if generation < self.gen {
+            return Err(HashlifeError::GenerationInPast { current: self.gen, requested: generation });
+        }
+        self.try_advance(generation - self.gen)
+    }
+
+    /// Advance the top node by `2^k` generations according to the edge rules.
+    /// The cache limits are enforced between steps, stopping at the first
+    /// step that cannot meet them.
+    ///
+    /// Invarient: self.top is not None
+    fn advance_pow2(&mut self, k: usize) -> Result<(), HashlifeError> {
+        let mut top = self.top.unwrap();
+        match self.edge {
             Edge::Infinite => {
+                // Living cells travel at most 2^k cells, which must fit in
+                // the border added around the top node.
+                while self.store.get(top).level < k + 1 {
+                    top = self.expand_empty_border(top);
+                }
                 // Expand
                 // given top level is n
                 // expanded level is n + 1
-                let expanded = self.expand_empty_border(Rc::clone(&top));
+                let expanded = self.expand_empty_border(top);
                 // expanded level is n + 2
-                let expanded = self.expand_empty_border(Rc::clone(&expanded));
+                let expanded = self.expand_empty_border(expanded);
                 // step level is n + 1
+                self.use_step_exponent(k);
                 let step = self.step(expanded);
                 // Check if there is population in the border
-                let g = step.get_grand_children();
-                let boarder_population = step.population - g.nwse.population - g.nesw.population - g.swne.population - g.senw.population;
-                if boarder_population == 0 {
+                let centre = self.store.centre(step);
+                let boarder_population = self.store.get(step).population - self.store.get(centre).population;
+                let next = if boarder_population == 0 {
                     // result level is n
-                    self.join(g.nwse, g.nesw, g.swne, g.senw)
+                    centre
                 } else {
                     // result level is n + 1
                     step
-                }
+                };
+                self.top = Some(next);
+                self.enforce_cache_limits()?;
             },
             Edge::Torus => {
-                let c = top.get_children();
-                let nw = Rc::clone(&c.nw);
-                let ne = Rc::clone(&c.ne);
-                let sw = Rc::clone(&c.sw);
-                let se = Rc::clone(&c.se);
-                let inverted = self.join(se, sw, ne, nw);
-                let inverted = || Rc::clone(&inverted);
-                let expanded = self.join(inverted(), inverted(), inverted(), inverted());
-                self.step(expanded)
+                // A single cell torus is stepped as the 2x2 torus it repeats
+                // into, then cut back to its north east cell.
+                let single = self.store.get(top).level == 0;
+                // The tiled torus can only be advanced by half of its width
+                // at a time, larger steps are repeated.
+                let max_exponent = self.store.get(top).level.max(1) - 1;
+                let (exponent, repeat) = if k <= max_exponent {
+                    (k, 1)
+                } else {
+                    (max_exponent, 1usize << (k - max_exponent))
+                };
+                for _ in 0..repeat {
+                    self.use_step_exponent(exponent);
+                    let tile = if single { self.join(top, top, top, top) } else { top };
+                    let c = self.store.split(tile);
+                    let inverted = self.join(c.se, c.sw, c.ne, c.nw);
+                    let expanded = self.join(inverted, inverted, inverted, inverted);
+                    let next = self.step(expanded);
+                    top = if single { self.store.split(next).ne } else { next };
+                    self.top = Some(top);
+                    self.enforce_cache_limits()?;
+                }
             },
             Edge::Truncate => {
-                let expanded = self.expand_empty_border(Rc::clone(&top));
-                self.step(expanded)
+                // Cells leaving the universe are removed every generation, so
+                // generations cannot be skipped. A single cell needs a second
+                // border to be stepped, and is cut back out of the result.
+                let single = self.store.get(top).level == 0;
+                for _ in 0..(1usize << k) {
+                    self.use_step_exponent(0);
+                    let mut expanded = self.expand_empty_border(top);
+                    if single {
+                        expanded = self.expand_empty_border(expanded);
+                    }
+                    let next = self.step(expanded);
+                    top = if single { self.store.split(next).ne } else { next };
+                    self.top = Some(top);
+                    self.enforce_cache_limits()?;
+                }
             },
-        };
-        self.top = Some(next);
-        self.gen += 1;
+        }
+        Ok(())
     }
 
-    fn make_automata(&mut self, a: Automata) -> Rc<Node> {
+    fn make_automata(&mut self, a: Automata) -> NodeId {
         match a {
-            Automata::Dead => {
-                if let Some(ref_to_node) = &self.cache.dead {
-                    Rc::clone(ref_to_node)
-                } else {
-                    let mut state = DefaultHasher::new();
-                    a.hash(&mut state);
-                    let node = Rc::new(Node {
-                        level: 0,
-                        population: a as usize,
-                        children: None,
-                        hash: state.finish(),
-                    });
-                    self.cache.dead = Some(Rc::clone(&node));
-                    node
-                }
-            },
-            Automata::Alive => {
-                if let Some(ref_to_node) = &self.cache.alive {
-                    Rc::clone(ref_to_node)
-                } else {
-                    let mut state = DefaultHasher::new();
-                    a.hash(&mut state);
-                    let node = Rc::new(Node {
-                        level: 0,
-                        population: a as usize,
-                        children: None,
-                        hash: state.finish(),
-                    });
-                    self.cache.alive = Some(Rc::clone(&node));
-                    node
-                }
-            },
+            Automata::Dead => NodeId::DEAD,
+            Automata::Alive => NodeId::ALIVE,
         }
     }
 
     /// Construct a Hashlife program given an array of states.
     pub fn from_array(buffer: Vec<u8>, width: usize, height: usize, edge: Edge) -> Self {
-        assert_eq!(buffer.len(), width * height);
-        //
-        let mut hashlife = Hashlife::new();
+        Self::from_array_with_rule(buffer, width, height, edge, Rule::default())
+    }
 
-        hashlife.edge = edge;
+    /// Construct a Hashlife program given an array of states that evolves by
+    /// the given rule.
+    pub fn from_array_with_rule(buffer: Vec<u8>, width: usize, height: usize, edge: Edge, rule: Rule) -> Self {
+        Self::try_from_array_with_rule(buffer, width, height, edge, rule).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `from_array`, but fails when the buffer does not hold `width *
+    /// height` valid states.
+    pub fn try_from_array(buffer: Vec<u8>, width: usize, height: usize, edge: Edge) -> Result<Self, HashlifeError> {
+        Self::try_from_array_with_rule(buffer, width, height, edge, Rule::default())
+    }
+
+    /// Like `from_array_with_rule`, but fails when the buffer does not hold
+    /// `width * height` valid states.
+    pub fn try_from_array_with_rule(buffer: Vec<u8>, width: usize, height: usize, edge: Edge, rule: Rule) -> Result<Self, HashlifeError> {
+        HashlifeBuilder::new().rule(rule).edge(edge).array(buffer, width, height).build()
+    }
 
+    /// Build the tree of a buffer already checked to hold `width * height`
+    /// valid states, centred on the origin.
+    fn construct_from_array(&mut self, buffer: &[u8], width: usize, height: usize) -> NodeId {
         // center on x-axis and negative on left
         let left = -(width as isize / 2);
         let right = width as isize + left - 1;
@@ -453,50 +514,53 @@ impl Hashlife {
         // assert_eq!(bound.height(), height);
 
         if size == 0 {
-            hashlife.top = Some(hashlife.make_automata(Automata::from(buffer[0] as usize)));
-            return hashlife;
+            return self.make_automata(Automata::from(buffer[0] as usize));
         }
 
         // Pack some configuration parameters to build the first generation.
         let params = ConstructionParameters {
-            level: size,
-            vector: &buffer,
+            vector: buffer,
             width,
             height,
             bound,
         };
 
-        let nw = hashlife.construct(-1, 0, size - 1, &params);
-        let ne= hashlife.construct(0, 0, size - 1, &params);
-        let sw= hashlife.construct(-1, -1, size - 1, &params);
-        let se= hashlife.construct(0, -1, size - 1, &params);
-        let top = hashlife.join(nw, ne, sw, se);
-
-        hashlife.top = Some(top);
-        hashlife
+        let nw = self.construct(-1, 0, size - 1, &params);
+        let ne= self.construct(0, 0, size - 1, &params);
+        let sw= self.construct(-1, -1, size - 1, &params);
+        let se= self.construct(0, -1, size - 1, &params);
+        self.join(nw, ne, sw, se)
     }
 
     /// Recursively build a Quad tree.
-    fn construct(&mut self, x: isize, y: isize, level: usize, params: &ConstructionParameters) -> Rc<Node> {
-        // Base case: retrieve value from cell
-        if level == 0 {
-            let bound = BoundingBox::new(x, y, level);
-            if !bound.collides(&params.bound) {
-                return self.empty(0);
+    fn construct(&mut self, x: isize, y: isize, level: usize, params: &ConstructionParameters) -> NodeId {
+        // Base case: retrieve the cells of a leaf
+        if level <= LEAF_LEVEL {
+            let area = BoundingBox::new(x, y, level);
+            let side = 1 << level;
+            let mut bits = 0;
+            for row in 0..side {
+                for col in 0..side {
+                    let (cx, cy) = (area.left + col as isize, area.top - row as isize);
+                    if !params.bound.contains(cx, cy) {
+                        continue;
+                    }
+                    let xidx = (cx - params.bound.left) as usize;
+                    let yidx = params.height - 1 - (cy - params.bound.bottom) as usize;
+                    let idx = params.width * yidx + xidx;
+                    let v = params.vector[idx];
+                    let a = Automata::from(v as usize);
+                    bits |= (a as u64) << (row * 8 + col);
+                }
             }
-            let xidx = (x - params.bound.left) as usize;
-            let yidx = params.height - 1 - (y - params.bound.bottom) as usize;
-            let idx = params.width * yidx + xidx;
-            let v = params.vector[idx];
-            let a = Automata::from(v as usize);
-            return self.make_automata(a);
+            return self.store.leaf(level, bits);
         }
 
         // Small helper function, speed up construction if building an empty region.
         let mut assemble = |dx, dy| {
             let bound = BoundingBox::new(x, y, level-1);
             if bound.collides(&params.bound) {
-                self.construct(x * 2 + dx, y * 2 + dy, level - 1, &params)
+                self.construct(x * 2 + dx, y * 2 + dy, level - 1, params)
             } else {
                 self.empty(level - 1)
             }
@@ -511,97 +575,206 @@ impl Hashlife {
     }
 
     /// Construct an empty Quad Node at the specified level.
-    fn empty(&mut self, level: usize) -> Rc<Node> {
+    fn empty(&mut self, level: usize) -> NodeId {
         // Base case
-        if level == 0 {
-            return self.make_automata(Automata::Dead);
+        if level <= LEAF_LEVEL {
+            return self.store.leaf(level, 0);
         }
-        // Construct children.
+        // Construct children, the node table returns the existing node.
         let child = self.empty(level - 1);
-        let children = Children {
-            nw: Rc::clone(&child),
-            ne: Rc::clone(&child),
-            sw: Rc::clone(&child),
-            se: Rc::clone(&child),
-        };
-        let hash = calculate_hash(&children);
-        // Check if node already exists in the cache.
-        if let Some(ref_to_node) = self.cache.join.get(&hash) {
-            return Rc::clone(ref_to_node);
-        };
-        let empty = Rc::new(Node {
-            level,
-            population: 0,
-            children: Some(children),
-            hash,
-        });
-        // Add node to cache.
-        self.cache.join.insert(hash, Rc::clone(&empty));
-        empty
-    }
-
-    fn get_node_with(&self, x: isize, y: isize, positions: &Vec<(isize, isize)>, node: Rc<Node>) -> Automata {
-        if node.level == 0 {
-            return node.as_automata();
+        self.join(child, child, child, child)
+    }
+
+    /// The state of the cell at `x`, `y`. Cells outside of the tree are dead
+    /// and a torus wraps the coordinates around. Returns `None` only when the
+    /// universe has no tree yet.
+    pub fn get(&self, x: isize, y: isize) -> Option<Automata> {
+        let top = self.top?;
+        let level = self.store.get(top).level;
+        let (x, y) = if self.edge == Edge::Torus { wrap(level, x, y) } else { (x, y) };
+        if !BoundingBox::square(level).contains(x, y) {
+            return Some(Automata::Dead);
         }
-        let position = positions[node.level-1];
-        let nw = (x*2, y*2+1);
-        let ne = (x*2+1, y*2+1);
-        let sw = (x*2, y*2);
-        let se = (x*2+1, y*2);
-        let children = node.get_children();
-        if position == nw {
-            return self.get_node_with(nw.0, nw.1, positions, Rc::clone(&children.nw));
-        } else if position == ne {
-            return self.get_node_with(ne.0, ne.1, positions, Rc::clone(&children.ne));
-        } else if position == sw {
-            return self.get_node_with(sw.0, sw.1, positions, Rc::clone(&children.sw));
-        } else if position == se {
-            return self.get_node_with(se.0, se.1, positions, Rc::clone(&children.se));
-        } else {
-            panic!("invalid coordinate calculated");
+        let mut node = top;
+        let (mut left, mut top) = top_corner(level);
+        loop {
+            let n = self.store.get(node);
+            let c = match n.content {
+                Content::Leaf(bits) => return Some(leaf_cell(bits, left, top, x, y)),
+                Content::Branch(c) => c,
+            };
+            let half = 1isize << (n.level - 1);
+            let (east, south) = (x >= left + half, y <= top - half);
+            node = match (east, south) {
+                (false, false) => c.nw,
+                (true, false) => c.ne,
+                (false, true) => c.sw,
+                (true, true) => c.se,
+            };
+            if east {
+                left += half;
+            }
+            if south {
+                top -= half;
+            }
         }
     }
 
-    pub fn get(&self, x: isize, y: isize) -> Option<Automata> {
-        let top = if let Some(top) = self.top.as_ref() {
-            Rc::clone(top)
-        } else {
-            return None;
-        };
+    /// The states of the cells inside a rectangle, row by row from the top
+    /// left, read like `get`. Only the living cells are visited.
+    pub fn get_region(&self, area: &BoundingBox) -> Vec<Automata> {
+        self.try_get_region(area).unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        let mut positions = Vec::with_capacity(top.level);
-        let mut xx = x;
-        let mut yy = y;
-        for _ in 0..top.level {
-            positions.push((xx,yy));
-            xx = xx.div_euclid(2);
-            yy = yy.div_euclid(2);
+    /// Like `get_region`, but fails when the rectangle is inside out or has
+    /// too many cells to allocate.
+    pub fn try_get_region(&self, area: &BoundingBox) -> Result<Vec<Automata>, HashlifeError> {
+        if area.top < area.bottom || area.right < area.left {
+            return Err(HashlifeError::InvalidRectangle);
+        }
+        // The differences are taken as unsigned so a rectangle spanning the
+        // whole plane does not overflow.
+        let width = (area.right.wrapping_sub(area.left) as usize).checked_add(1);
+        let height = (area.top.wrapping_sub(area.bottom) as usize).checked_add(1);
+        let count = width.zip(height).and_then(|(w, h)| w.checked_mul(h)).ok_or(HashlifeError::TooLarge)?;
+        let mut cells = Vec::new();
+        cells.try_reserve_exact(count).map_err(|_| HashlifeError::TooLarge)?;
+        cells.resize(count, Automata::Dead);
+        self.for_each_live_in(area, |x, y| cells[area.index(x, y)] = Automata::Alive);
+        Ok(cells)
+    }
+
+    /// Call `f` with the coordinates of every living cell inside a
+    /// rectangle, read like `get` so a torus repeats its cells over the
+    /// plane.
+    pub(crate) fn for_each_live_in<F: FnMut(isize, isize)>(&self, area: &BoundingBox, mut f: F) {
+        let top = match self.top {
+            Some(top) => top,
+            None => return,
+        };
+        if self.edge != Edge::Torus {
+            for (x, y) in self.live_cells_in(area) {
+                f(x, y);
+            }
+            return;
         }
+        // A torus tiles the plane with its tree. The parts of the tree the
+        // rectangle wraps onto are read once, however many periods it spans,
+        // and their cells are repeated across it.
+        let level = self.store.get(top).level;
+        let (side, square) = (1isize << level, BoundingBox::square(level));
+        for &(left, right) in &wrapped_ranges(side, square.left, area.left, area.right) {
+            for &(bottom, top) in &wrapped_ranges(side, square.bottom, area.bottom, area.top) {
+                for (x, y) in self.live_cells_in(&BoundingBox::from(top, bottom, left, right)) {
+                    for x in copies(side, x, area.left, area.right) {
+                        for y in copies(side, y, area.bottom, area.top) {
+                            f(x, y);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set the state of the cell at `x`, `y`. Only the nodes on the path from
+    /// the cell to the top are rebuilt. An infinite universe grows to include
+    /// the cell and a torus wraps the coordinates around.
+    ///
+    /// Panics if the cell is outside of a truncated universe, or more than
+    /// 2^61 cells from the origin of an infinite one.
+    pub fn set(&mut self, x: isize, y: isize, state: Automata) {
+        self.try_set(x, y, state).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Flip the cell at `x`, `y` between dead and alive, like `set`.
+    pub fn toggle(&mut self, x: isize, y: isize) {
+        self.try_toggle(x, y).unwrap_or_else(|err| panic!("{}", err));
+    }
 
-        // TODO: what if level == 0?
-        let children = top.get_children();
+    /// Like `set`, but fails instead of panicking when the cell is outside
+    /// of the universe.
+    pub fn try_set(&mut self, x: isize, y: isize, state: Automata) -> Result<(), HashlifeError> {
+        self.check_inside(x, y)?;
+        self.update(x, y, |_| state);
+        Ok(())
+    }
+
+    /// Like `toggle`, but fails instead of panicking when the cell is outside
+    /// of the universe.
+    pub fn try_toggle(&mut self, x: isize, y: isize) -> Result<(), HashlifeError> {
+        self.check_inside(x, y)?;
+        self.update(x, y, |state| if state.is_alive() { Automata::Dead } else { Automata::Alive });
+        Ok(())
+    }
 
-        if y < 0 {
-            if x < 0 { // SW
-                Some(self.get_node_with(-1, -1, &positions, Rc::clone(&children.sw)))
-            } else { // SE
-                Some(self.get_node_with(0, -1, &positions, Rc::clone(&children.se)))
+    /// Fail on a cell outside of a truncated universe, or further from the
+    /// origin than the tallest tree of an infinite one reaches. A torus wraps
+    /// every cell inside.
+    fn check_inside(&self, x: isize, y: isize) -> Result<(), HashlifeError> {
+        let level = match self.edge {
+            Edge::Infinite => MAX_LEVEL,
+            Edge::Torus => return Ok(()),
+            Edge::Truncate => self.top.map_or(0, |top| self.store.get(top).level),
+        };
+        if !BoundingBox::square(level).contains(x, y) {
+            return Err(HashlifeError::OutOfBounds { x, y });
+        }
+        Ok(())
+    }
+
+    /// Apply `f` to the cell at `x`, `y`, which `check_inside` accepted.
+    fn update<F: Fn(Automata) -> Automata>(&mut self, x: isize, y: isize, f: F) {
+        let mut top = match self.top {
+            Some(top) => top,
+            None => self.empty(0),
+        };
+        let (mut x, mut y) = (x, y);
+        loop {
+            let side = 1isize << self.store.get(top).level;
+            let (left, top_y) = top_corner(self.store.get(top).level);
+            if left <= x && x < left + side && top_y - side < y && y <= top_y {
+                break;
             }
-        } else {
-            if x < 0 { // NW
-                Some(self.get_node_with(-1, 0, &positions, Rc::clone(&children.nw)))
-            } else { // NE
-                Some(self.get_node_with(0, 0, &positions, Rc::clone(&children.ne)))
+            match self.edge {
+                Edge::Infinite => top = self.expand_empty_border(top),
+                Edge::Torus => (x, y) = wrap(self.store.get(top).level, x, y),
+                Edge::Truncate => unreachable!("cell ({}, {}) is outside of the universe", x, y),
             }
         }
+        let (left, top_y) = top_corner(self.store.get(top).level);
+        let top = self.update_node(top, left, top_y, x, y, &f);
+        self.top = Some(top);
+    }
+
+    /// Rebuild the path to the cell at `x`, `y` in a node whose top left
+    /// cell is at `left`, `top`.
+    fn update_node<F: Fn(Automata) -> Automata>(&mut self, node: NodeId, left: isize, top: isize, x: isize, y: isize, f: &F) -> NodeId {
+        let n = *self.store.get(node);
+        match n.content {
+            Content::Leaf(bits) => {
+                let bit = (top - y) * 8 + (x - left);
+                let state = f(Automata::from((bits >> bit & 1) as usize));
+                self.store.leaf(n.level, bits & !(1 << bit) | (state as u64) << bit)
+            },
+            Content::Branch(c) => {
+                let half = 1isize << (n.level - 1);
+                let Children { mut nw, mut ne, mut sw, mut se } = c;
+                match (x >= left + half, y <= top - half) {
+                    (false, false) => nw = self.update_node(nw, left, top, x, y, f),
+                    (true, false) => ne = self.update_node(ne, left + half, top, x, y, f),
+                    (false, true) => sw = self.update_node(sw, left, top - half, x, y, f),
+                    (true, true) => se = self.update_node(se, left + half, top - half, x, y, f),
+                }
+                self.join(nw, ne, sw, se)
+            },
+        }
     }
 
     /// Returns the maximum node level in the tree. Setting n to the result,
     /// the number of levels is n + 1.
     fn max_level(&self) -> usize {
-        if let Some(top) = &self.top {
-            top.level
+        if let Some(top) = self.top {
+            self.store.get(top).level
         } else {
             0
         }
@@ -612,116 +785,143 @@ impl Hashlife {
     pub fn draw_diff_to_viewport_array(&mut self, buffer: &mut [u8], viewport: BoundingBox) {
         // case where the cell only contains 1 level.
         if self.max_level() == 0 {
-            if let Some(top) = self.top.as_ref() {
-                buffer[0] = top.population as u8;
+            if let Some(top) = self.top {
+                buffer[0] = self.store.get(top).population as u8;
             }
             return;
         }
-        let top = if let Some(top) = &self.top {
-            Rc::clone(top)
+        let top = if let Some(top) = self.top {
+            top
         } else {
             return;
         };
-        let previous = if let Some(previous) = &self.previous {
-            if previous.level == 0 {
+        let previous = if let Some(previous) = self.previous {
+            if self.store.get(previous).level == 0 {
                 return;
             }
-            Rc::clone(previous)
+            previous
         } else {
             return;
         };
+        let (t, p) = (self.store.get(top), self.store.get(previous));
+        match (t.content, p.content) {
+            (Content::Leaf(bits), Content::Leaf(previous_bits)) if t.level == p.level => {
+                let (left, top) = top_corner(t.level);
+                self.draw_leaf(buffer, bits, bits ^ previous_bits, t.level, &viewport, left, top);
+                return;
+            },
+            (Content::Branch(_), Content::Branch(_)) => (),
+            // The universe changed shape, everything is drawn.
+            _ => {
+                self.draw_to_viewport_buffer(buffer, viewport);
+                return;
+            },
+        }
 
-        let top_children = top.get_children();
-        let previous_children = previous.get_children();
-        let t_nw = Rc::clone(&top_children.nw);
-        let t_ne = Rc::clone(&top_children.ne);
-        let t_sw = Rc::clone(&top_children.sw);
-        let t_se = Rc::clone(&top_children.se);
-        let p_nw = Rc::clone(&previous_children.nw);
-        let p_ne = Rc::clone(&previous_children.ne);
-        let p_sw = Rc::clone(&previous_children.sw);
-        let p_se = Rc::clone(&previous_children.se);
-        if t_nw != p_nw {
-            self.draw_diff_to_cell(buffer, t_nw, p_nw, &viewport, -1, 0);
+        let t = self.store.children(top);
+        let p = self.store.children(previous);
+        if t.nw != p.nw {
+            self.draw_diff_to_cell(buffer, t.nw, p.nw, &viewport, -1, 0);
         }
-        if t_ne != p_ne {
-            self.draw_diff_to_cell(buffer, t_ne, p_ne, &viewport, 0, 0);
+        if t.ne != p.ne {
+            self.draw_diff_to_cell(buffer, t.ne, p.ne, &viewport, 0, 0);
         }
-        if t_sw != p_sw {
-            self.draw_diff_to_cell(buffer, t_sw, p_sw, &viewport, -1, -1);
+        if t.sw != p.sw {
+            self.draw_diff_to_cell(buffer, t.sw, p.sw, &viewport, -1, -1);
         }
-        if t_se != p_se {
-            self.draw_diff_to_cell(buffer, t_se, p_se, &viewport, 0, -1);
+        if t.se != p.se {
+            self.draw_diff_to_cell(buffer, t.se, p.se, &viewport, 0, -1);
         }
     }
 
     /// Helper function for drawing the node to the buffer. Children of the node
     /// will not be drawn if they are equal to the previous respective children.
-    fn draw_diff_to_cell(&mut self, buffer: &mut [u8], node: Rc<Node>, previous: Rc<Node>, viewport: &BoundingBox, x: isize, y: isize) {
-        let area = BoundingBox::new(x, y, node.level);
-        if !area.collides(&viewport) {
+    fn draw_diff_to_cell(&self, buffer: &mut [u8], node: NodeId, previous: NodeId, viewport: &BoundingBox, x: isize, y: isize) {
+        let n = self.store.get(node);
+        let area = BoundingBox::new(x, y, n.level);
+        if !area.collides(viewport) {
             return;
         }
 
-        if node.level == 0 {
-            buffer[viewport.index(x, y)] = node.population as u8;
+        if let Content::Leaf(bits) = n.content {
+            let changed = bits ^ self.store.get(previous).bits();
+            self.draw_leaf(buffer, bits, changed, n.level, viewport, area.left, area.top);
         } else {
-            let mut draw_down = |dx: isize, dy: isize, n: Rc<Node>, p: Rc<Node>| {
+            let mut draw_down = |dx: isize, dy: isize, n: NodeId, p: NodeId| {
                 if n == p { return; }
-                self.draw_diff_to_cell(&mut buffer[..], n, p, &viewport, 2*x+dx, 2*y+dy);
+                self.draw_diff_to_cell(&mut buffer[..], n, p, viewport, 2*x+dx, 2*y+dy);
             };
-            let c = node.get_children();
-            let pc = previous.get_children();
-            draw_down(0, 1, Rc::clone(&c.nw), Rc::clone(&pc.nw));
-            draw_down(1, 1, Rc::clone(&c.ne), Rc::clone(&pc.ne));
-            draw_down(0, 0, Rc::clone(&c.sw), Rc::clone(&pc.sw));
-            draw_down(1, 0, Rc::clone(&c.se), Rc::clone(&pc.se));
+            let c = self.store.children(node);
+            let pc = self.store.children(previous);
+            draw_down(0, 1, c.nw, pc.nw);
+            draw_down(1, 1, c.ne, pc.ne);
+            draw_down(0, 0, c.sw, pc.sw);
+            draw_down(1, 0, c.se, pc.se);
         }
     }
 
     pub fn draw_to_viewport_buffer(&mut self, buffer: &mut [u8], viewport: BoundingBox) {
         if self.max_level() == 0 {
-            if let Some(top) = self.top.as_ref() {
-                buffer[0] = top.population as u8;
+            if let Some(top) = self.top {
+                buffer[0] = self.store.get(top).population as u8;
             }
             return;
         }
-        let top = Rc::clone(self.top.as_ref().unwrap());
-        let c = top.get_children();
-        let nw = Rc::clone(&c.nw);
-        let ne = Rc::clone(&c.ne);
-        let sw = Rc::clone(&c.sw);
-        let se = Rc::clone(&c.se);
-        self.draw_to_cell(buffer, nw, &viewport, -1, 0);
-        self.draw_to_cell(buffer, ne, &viewport, 0, 0);
-        self.draw_to_cell(buffer, sw, &viewport, -1, -1);
-        self.draw_to_cell(buffer, se, &viewport, 0, -1);
+        let top = self.top.unwrap();
+        let n = self.store.get(top);
+        if let Content::Leaf(bits) = n.content {
+            let (left, top) = top_corner(n.level);
+            self.draw_leaf(buffer, bits, !0, n.level, &viewport, left, top);
+            return;
+        }
+        let c = self.store.children(top);
+        self.draw_to_cell(buffer, c.nw, &viewport, -1, 0);
+        self.draw_to_cell(buffer, c.ne, &viewport, 0, 0);
+        self.draw_to_cell(buffer, c.sw, &viewport, -1, -1);
+        self.draw_to_cell(buffer, c.se, &viewport, 0, -1);
     }
 
     /// Helper function for drawing the entire tree to a buffer
-    fn draw_to_cell(&mut self, buffer: &mut [u8], node: Rc<Node>, viewport: &BoundingBox, x: isize, y: isize) {
-        let area = BoundingBox::new(x, y, node.level);
-        if !area.collides(&viewport) {
+    fn draw_to_cell(&self, buffer: &mut [u8], node: NodeId, viewport: &BoundingBox, x: isize, y: isize) {
+        let n = self.store.get(node);
+        let area = BoundingBox::new(x, y, n.level);
+        if !area.collides(viewport) {
             return;
         }
 
-        if node.level == 0 {
-            buffer[viewport.index(x, y)] = node.population as u8;
+        if let Content::Leaf(bits) = n.content {
+            self.draw_leaf(buffer, bits, !0, n.level, viewport, area.left, area.top);
         } else {
-            let mut draw_down = |dx: isize, dy: isize, n: Rc<Node>| {
-                self.draw_to_cell(&mut buffer[..], n, &viewport, 2*x+dx, 2*y+dy);
+            let mut draw_down = |dx: isize, dy: isize, n: NodeId| {
+                self.draw_to_cell(&mut buffer[..], n, viewport, 2*x+dx, 2*y+dy);
             }; 
-            let c = node.get_children();
-            draw_down(0, 1, Rc::clone(&c.nw));
-            draw_down(1, 1, Rc::clone(&c.ne));
-            draw_down(0, 0, Rc::clone(&c.sw));
-            draw_down(1, 0, Rc::clone(&c.se));
+            let c = self.store.children(node);
+            draw_down(0, 1, c.nw);
+            draw_down(1, 1, c.ne);
+            draw_down(0, 0, c.sw);
+            draw_down(1, 0, c.se);
+        }
+    }
+
+    /// Draw the cells of a leaf whose top left cell is at `left`, `top`. Only
+    /// the cells set in `mask` are drawn.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_leaf(&self, buffer: &mut [u8], bits: u64, mask: u64, level: usize, viewport: &BoundingBox, left: isize, top: isize) {
+        let side = 1 << level;
+        for row in 0..side {
+            for col in 0..side {
+                let (x, y) = (left + col as isize, top - row as isize);
+                if mask >> (row * 8 + col) & 1 == 1 && viewport.contains(x, y) {
+                    buffer[viewport.index(x, y)] = (bits >> (row * 8 + col) & 1) as u8;
+                }
+            }
         }
     }
 
+    #[cfg(test)]
     fn as_vector(&self) -> Vec<Automata> {
-        if let Some(top) = &self.top {
-            top.as_array().into_iter().flatten().collect()
+        if let Some(top) = self.top {
+            self.store.as_array(top).into_iter().flatten().collect()
         } else {
             vec![]
         }
@@ -733,134 +933,63 @@ impl Hashlife {
 }
 
 
-impl Node {
-
-    fn get_children(&self) ->&Children {
-        &self.children.as_ref().unwrap()
-    }
-
-    fn get_grand_children(&self) -> GrandChildren {
-        let err1 = "unable to unwrap child (and expecting grand-children)";
-        let err2 = "unable to unwrap grand-children";
-        GrandChildren {
-            nwnw: Rc::clone(&self.children.as_ref().expect(err1).nw.children.as_ref().expect(err2).nw),
-            nwne: Rc::clone(&self.children.as_ref().expect(err1).nw.children.as_ref().expect(err2).ne),
-            nwsw: Rc::clone(&self.children.as_ref().expect(err1).nw.children.as_ref().expect(err2).sw),
-            nwse: Rc::clone(&self.children.as_ref().expect(err1).nw.children.as_ref().expect(err2).se),
-            nenw: Rc::clone(&self.children.as_ref().expect(err1).ne.children.as_ref().expect(err2).nw),
-            nene: Rc::clone(&self.children.as_ref().expect(err1).ne.children.as_ref().expect(err2).ne),
-            nesw: Rc::clone(&self.children.as_ref().expect(err1).ne.children.as_ref().expect(err2).sw),
-            nese: Rc::clone(&self.children.as_ref().expect(err1).ne.children.as_ref().expect(err2).se),
-            swnw: Rc::clone(&self.children.as_ref().expect(err1).sw.children.as_ref().expect(err2).nw),
-            swne: Rc::clone(&self.children.as_ref().expect(err1).sw.children.as_ref().expect(err2).ne),
-            swsw: Rc::clone(&self.children.as_ref().expect(err1).sw.children.as_ref().expect(err2).sw),
-            swse: Rc::clone(&self.children.as_ref().expect(err1).sw.children.as_ref().expect(err2).se),
-            senw: Rc::clone(&self.children.as_ref().expect(err1).se.children.as_ref().expect(err2).nw),
-            sene: Rc::clone(&self.children.as_ref().expect(err1).se.children.as_ref().expect(err2).ne),
-            sesw: Rc::clone(&self.children.as_ref().expect(err1).se.children.as_ref().expect(err2).sw),
-            sese: Rc::clone(&self.children.as_ref().expect(err1).se.children.as_ref().expect(err2).se),
-        }
-    }
-
-    fn get_grand_automata(&self) -> GrandAutomata {
-        if self.level != 2 {
-            panic!("node must be at level 2 to get automatas");
-        }
-        let grand_children = self.get_grand_children();
-        GrandAutomata {
-            nwnw: grand_children.nwnw.as_automata(),
-            nwne: grand_children.nwne.as_automata(),
-            nwsw: grand_children.nwsw.as_automata(),
-            nwse: grand_children.nwse.as_automata(),
-            nenw: grand_children.nenw.as_automata(),
-            nene: grand_children.nene.as_automata(),
-            nesw: grand_children.nesw.as_automata(),
-            nese: grand_children.nese.as_automata(),
-            swnw: grand_children.swnw.as_automata(),
-            swne: grand_children.swne.as_automata(),
-            swsw: grand_children.swsw.as_automata(),
-            swse: grand_children.swse.as_automata(),
-            senw: grand_children.senw.as_automata(),
-            sene: grand_children.sene.as_automata(),
-            sesw: grand_children.sesw.as_automata(),
-            sese: grand_children.sese.as_automata(),
-        }
-    }
-
-    fn as_automata(&self) -> Automata {
-        Automata::from(self.population)
-    }
+/// The top left cell of a top node of the given level, the top node is
+/// centred on the origin.
+fn top_corner(level: usize) -> (isize, isize) {
+    let side = 1isize << level;
+    let left = -(side / 2);
+    (left, left + side - 1)
+}
 
-    fn from_automata(cell: Automata) -> Node {
-        let mut state = DefaultHasher::new();
-        cell.hash(&mut state);
-        Node {
-            level: 0,
-            population: cell as usize,
-            children: None,
-            hash: state.finish(),
-        }
+/// The cell of the tree of the given level a torus maps `x`, `y` to.
+fn wrap(level: usize, x: isize, y: isize) -> (isize, isize) {
+    let side = 1isize << level;
+    let (left, top) = top_corner(level);
+    // Reduced first so that no coordinate overflows.
+    let (x, y) = (x.rem_euclid(side), y.rem_euclid(side));
+    ((x - left).rem_euclid(side) + left, top - (top - y).rem_euclid(side))
+}
 
+/// The ranges of a torus of `side` cells from `start` that the cells from
+/// `low` to `high` of the plane wrap onto.
+fn wrapped_ranges(side: isize, start: isize, low: isize, high: isize) -> Vec<(isize, isize)> {
+    let end = start + side - 1;
+    if high.wrapping_sub(low) as usize >= (side - 1) as usize {
+        return vec![(start, end)];
     }
-
-    fn as_array(&self) -> Vec<Vec<Automata>> {
-        if self.level == 0 {
-            return vec![vec![self.as_automata()]];
-        }
-        let children = self.get_children();
-        let nw = children.nw.as_array();
-        let ne = children.ne.as_array();
-        let sw = children.sw.as_array();
-        let se = children.se.as_array();
-        let top = nw.into_iter()
-            .zip(ne.into_iter())
-            .map(|(left, right)| {
-                let mut result = Vec::with_capacity(left.len() + right.len());
-                result.extend(left);
-                result.extend(right);
-                result
-            })
-            .collect::<Vec<Vec<Automata>>>();
-        let bottom = sw.into_iter()
-            .zip(se.into_iter())
-            .map(|(left, right)| {
-                let mut result = Vec::with_capacity(left.len() + right.len());
-                result.extend(left);
-                result.extend(right);
-                result
-            })
-            .collect::<Vec<Vec<Automata>>>();
-        let mut rows = Vec::with_capacity(top.len() + bottom.len());
-        rows.extend(top);
-        rows.extend(bottom);
-        rows
+    let wrap = |at: isize| (at.rem_euclid(side) - start).rem_euclid(side) + start;
+    match (wrap(low), wrap(high)) {
+        (low, high) if low <= high => vec![(low, high)],
+        (low, high) => vec![(low, end), (start, high)],
     }
 }
 
-impl Hash for Node {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.hash.hash(state);
-    }
+/// The copies of a cell of a torus of `side` cells from `low` to `high` on
+/// the plane.
+fn copies(side: isize, cell: isize, low: isize, high: isize) -> impl Iterator<Item = isize> {
+    let offset = (cell.rem_euclid(side) - low.rem_euclid(side)).rem_euclid(side);
+    std::iter::successors(low.checked_add(offset), move |&at| at.checked_add(side)).take_while(move |&at| at <= high)
 }
 
-impl Children {
-    fn from(nw: &Rc<Node>, ne: &Rc<Node>, sw: &Rc<Node>, se: &Rc<Node>) -> Self {
-        Self {
-            nw: Rc::clone(nw),
-            ne: Rc::clone(ne),
-            sw: Rc::clone(sw),
-            se: Rc::clone(se),
-        }
-    }
+/// Read the cell at `x`, `y` from a leaf whose top left cell is at `left`,
+/// `top`.
+fn leaf_cell(bits: u64, left: isize, top: isize, x: isize, y: isize) -> Automata {
+    let col = (x - left) as usize;
+    let row = (top - y) as usize;
+    assert!(col < 8 && row < 8, "invalid coordinate calculated");
+    Automata::from((bits >> (row * 8 + col) & 1) as usize)
 }
 
-fn calculate_hash(children: &Children) -> u64 {
-    let mut state = DefaultHasher::new();
-    children.hash(&mut state);
-    state.finish()
+/// Numbers from a small linear congruential generator, so tests get the
+/// same soup of cells on every run.
+#[cfg(test)]
+fn random(mut seed: u32) -> impl Iterator<Item = u32> {
+    std::iter::repeat_with(move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        seed >> 16
+    })
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -953,7 +1082,7 @@ mod tests {
             0,0,1,0,
             0,1,0,1
         ];
-        let mut hashlife = Hashlife::from_array(cells, cell_width, cell_height, Edge::Truncate);
+        let hashlife = Hashlife::from_array(cells, cell_width, cell_height, Edge::Truncate);
         assert_eq!(hashlife.max_level(), 3);
 
         // two left most columns
@@ -1162,7 +1291,7 @@ mod tests {
             0,0,0,0,
         ];
         let mut hashlife = Hashlife::from_array(cells, cell_width, cell_height, Edge::Truncate);
-        let expanded = hashlife.expand_empty_border(Rc::clone(hashlife.top.as_ref().unwrap()));
+        let expanded = hashlife.expand_empty_border(hashlife.top.unwrap());
         let viewport = BoundingBox::from(1, -2, -2, 1);
         hashlife.top = Some(expanded);
         for x in -2..2 {
@@ -1181,4 +1310,459 @@ mod tests {
             }
         }
     }
+
+    fn glider_and_blinker() -> Vec<u8> {
+        vec![
+            0,1,0,0,0,0,0,0,
+            0,0,1,0,0,0,0,0,
+            1,1,1,0,0,0,0,0,
+            0,0,0,0,0,0,0,0,
+            0,0,0,0,0,0,0,0,
+            0,0,0,0,0,1,1,1,
+            0,0,0,0,0,0,0,0,
+            0,0,0,0,0,0,0,0,
+        ]
+    }
+
+    /// A glider flying north west, away from the blinker, so the blinker is
+    /// left alone however far the universe is advanced.
+    fn glider_leaving_blinker() -> Vec<u8> {
+        vec![
+            1,1,1,0,0,0,0,0,
+            1,0,0,0,0,0,0,0,
+            0,1,0,0,0,0,0,0,
+            0,0,0,0,0,0,0,0,
+            0,0,0,0,0,0,0,0,
+            0,0,0,0,0,1,1,1,
+            0,0,0,0,0,0,0,0,
+            0,0,0,0,0,0,0,0,
+        ]
+    }
+
+    fn render(hashlife: &mut Hashlife, viewport: BoundingBox) -> Vec<u8> {
+        let mut buffer = vec![0; viewport.width() * viewport.height()];
+        hashlife.draw_to_viewport_buffer(&mut buffer, viewport);
+        buffer
+    }
+
+    #[test]
+    fn step_pow2_matches_single_generations() {
+        for edge in [Edge::Infinite, Edge::Torus] {
+            let mut single = Hashlife::from_array(glider_and_blinker(), 8, 8, edge);
+            let mut jumped = Hashlife::from_array(glider_and_blinker(), 8, 8, edge);
+            for _ in 0..16 {
+                single.next_generation();
+            }
+            jumped.step_pow2(4);
+            assert_eq!(single.get_generation(), 16);
+            assert_eq!(jumped.get_generation(), 16);
+            let viewport = BoundingBox::from(15, -16, -16, 15);
+            assert_eq!(render(&mut single, BoundingBox::from(15, -16, -16, 15)), render(&mut jumped, viewport), "{:?}", edge);
+        }
+    }
+
+    #[test]
+    fn step_exponent_applies_to_next_generation() {
+        let mut single = Hashlife::from_array(glider_and_blinker(), 8, 8, Edge::Truncate);
+        let mut jumped = Hashlife::from_array(glider_and_blinker(), 8, 8, Edge::Truncate);
+        jumped.set_step_exponent(2);
+        assert_eq!(jumped.get_step_exponent(), 2);
+        for _ in 0..8 {
+            single.next_generation();
+        }
+        jumped.next_generation();
+        jumped.next_generation();
+        assert_eq!(jumped.get_generation(), 8);
+        assert_eq!(single.as_vector(), jumped.as_vector());
+    }
+
+    #[test]
+    fn step_results_are_kept_for_every_exponent() {
+        let mut hashlife = Hashlife::from_array(soup(), 16, 16, Edge::Infinite);
+        hashlife.advance(5);
+        let mut exponents = hashlife.cache.step.iter().filter(|(_, steps)| !steps.is_empty()).map(|(&k, _)| k).collect::<Vec<_>>();
+        exponents.sort_unstable();
+        assert_eq!(exponents, vec![0, 2]);
+    }
+
+    #[test]
+    fn advance_matches_single_generations() {
+        for edge in [Edge::Infinite, Edge::Torus, Edge::Truncate] {
+            let mut single = Hashlife::from_array(glider_leaving_blinker(), 8, 8, edge);
+            let mut advanced = Hashlife::from_array(glider_leaving_blinker(), 8, 8, edge);
+            for _ in 0..300 {
+                single.next_generation();
+            }
+            advanced.advance(300);
+            assert_eq!(advanced.get_generation(), 300);
+            let viewport = BoundingBox::from(127, -128, -128, 127);
+            assert_eq!(render(&mut single, BoundingBox::from(127, -128, -128, 127)), render(&mut advanced, viewport), "{:?}", edge);
+        }
+
+        // Universes of a single cell and of 2x2 cells, under a rule that
+        // keeps them changing.
+        let rule: Rule = "B1/S1".parse().unwrap();
+        for (cells, side) in [(vec![1], 1), (vec![1, 0, 0, 0], 2)] {
+            for edge in [Edge::Infinite, Edge::Torus, Edge::Truncate] {
+                let mut single = Hashlife::from_array_with_rule(cells.clone(), side, side, edge, rule.clone());
+                let mut advanced = Hashlife::from_array_with_rule(cells.clone(), side, side, edge, rule.clone());
+                let mut reached = Hashlife::from_array_with_rule(cells.clone(), side, side, edge, rule.clone());
+                for _ in 0..21 {
+                    single.next_generation();
+                }
+                advanced.advance(21);
+                reached.goto_generation(21);
+                let viewport = BoundingBox::from(31, -32, -32, 31);
+                let expected = render(&mut single, viewport);
+                assert_eq!(render(&mut advanced, viewport), expected, "{}x{} {:?}", side, side, edge);
+                assert_eq!(render(&mut reached, viewport), expected, "{}x{} {:?}", side, side, edge);
+            }
+        }
+    }
+
+    #[test]
+    fn goto_generation() {
+        let mut hashlife = Hashlife::from_array(glider_leaving_blinker(), 8, 8, Edge::Infinite);
+        hashlife.goto_generation(5);
+        assert_eq!(hashlife.get_generation(), 5);
+        hashlife.goto_generation(1_000_003);
+        assert_eq!(hashlife.get_generation(), 1_000_003);
+        // The glider has left, the blinker is vertical on odd generations.
+        assert_eq!(hashlife.get(2, -1), Some(Automata::Alive));
+        assert_eq!(hashlife.get(2, -2), Some(Automata::Alive));
+        assert_eq!(hashlife.get(2, -3), Some(Automata::Alive));
+        assert_eq!(hashlife.get(1, -2), Some(Automata::Dead));
+        assert_eq!(hashlife.get(3, -2), Some(Automata::Dead));
+    }
+
+    #[test]
+    /// Branches are only shared when their children are identical.
+    fn join_is_canonical() {
+        let mut hashlife = Hashlife::new();
+        let dead = hashlife.join(NodeId::DEAD, NodeId::DEAD, NodeId::DEAD, NodeId::DEAD);
+        let alive = hashlife.join(NodeId::ALIVE, NodeId::ALIVE, NodeId::ALIVE, NodeId::ALIVE);
+        assert_ne!(dead, alive);
+        assert_eq!(hashlife.store.get(dead).population, 0);
+        assert_eq!(hashlife.store.get(alive).population, 4);
+        assert_eq!(dead, hashlife.join(NodeId::DEAD, NodeId::DEAD, NodeId::DEAD, NodeId::DEAD));
+        assert_eq!(dead, hashlife.empty(1));
+        assert_eq!(hashlife.node_count(), 2);
+    }
+
+    /// A 16x16 soup of random cells.
+    fn soup() -> Vec<u8> {
+        random(12345).take(16 * 16).map(|n| (n & 1) as u8).collect()
+    }
+
+    /// Advance a square grid by one generation, cells outside of it are dead.
+    fn brute_force(cells: &[u8], size: usize, rule: &Rule) -> Vec<u8> {
+        let mut next = vec![0; size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let mut neighbors = 0;
+                for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    if nx >= 0 && ny >= 0 && (nx as usize) < size && (ny as usize) < size {
+                        neighbors += cells[ny as usize * size + nx as usize] as usize;
+                    }
+                }
+                let alive = if cells[y * size + x] == 1 {
+                    rule.survival().contains(&neighbors)
+                } else {
+                    rule.birth().contains(&neighbors)
+                };
+                next[y * size + x] = alive as u8;
+            }
+        }
+        next
+    }
+
+    #[test]
+    fn rules_match_brute_force() {
+        for rule in ["B3/S23", "B36/S23", "B3678/S34678", "B2/S", "B3/S012345678"] {
+            let rule: Rule = rule.parse().unwrap();
+            let mut hashlife = Hashlife::from_array_with_rule(soup(), 16, 16, Edge::Truncate, rule.clone());
+            assert_eq!(hashlife.get_rule(), &rule);
+            let mut expected = soup();
+            for _ in 0..20 {
+                hashlife.next_generation();
+                expected = brute_force(&expected, 16, &rule);
+                assert_eq!(render(&mut hashlife, BoundingBox::from(7, -8, -8, 7)), expected, "{}", rule);
+            }
+        }
+    }
+
+    #[test]
+    fn set_rule_discards_memoised_results() {
+        let highlife: Rule = "B36/S23".parse().unwrap();
+        let mut hashlife = Hashlife::from_array(soup(), 16, 16, Edge::Truncate);
+        let mut expected = soup();
+        for _ in 0..5 {
+            hashlife.next_generation();
+            expected = brute_force(&expected, 16, &Rule::life());
+        }
+        hashlife.set_rule(highlife.clone());
+        for _ in 0..5 {
+            hashlife.next_generation();
+            expected = brute_force(&expected, 16, &highlife);
+        }
+        assert_eq!(render(&mut hashlife, BoundingBox::from(7, -8, -8, 7)), expected);
+    }
+
+    #[test]
+    fn leaves_are_bitboards() {
+        let mut hashlife = Hashlife::from_array(soup(), 16, 16, Edge::Truncate);
+        let top = hashlife.top.unwrap();
+        assert_eq!(hashlife.store.get(top).level, 4);
+        // A single branch over four 8x8 leaves.
+        assert_eq!(hashlife.node_count(), 5);
+        let c = hashlife.store.children(top);
+        assert!(matches!(hashlife.store.get(c.nw).content, Content::Leaf(_)));
+        assert_eq!(render(&mut hashlife, BoundingBox::from(7, -8, -8, 7)), soup());
+        for (i, &cell) in soup().iter().enumerate() {
+            let (x, y) = ((i % 16) as isize - 8, 7 - (i / 16) as isize);
+            assert_eq!(hashlife.get(x, y), Some(Automata::from(cell as usize)));
+        }
+    }
+
+    #[test]
+    fn split_leaf_joins_back() {
+        let mut hashlife = Hashlife::new();
+        let leaf = hashlife.store.leaf(3, 0x8142_2418_1824_4281);
+        let c = hashlife.store.split(leaf);
+        assert_eq!(hashlife.store.get(c.nw).bits(), 0x0804_0201);
+        assert_eq!(hashlife.join(c.nw, c.ne, c.sw, c.se), leaf);
+        let centre = hashlife.store.centre(leaf);
+        assert_eq!(hashlife.store.get(centre).bits(), 0x0906_0609);
+    }
+
+    #[test]
+    fn set_and_toggle() {
+        let mut hashlife = Hashlife::from_array(vec![0; 16], 4, 4, Edge::Infinite);
+        hashlife.set(-1, 0, Automata::Alive);
+        hashlife.set(0, 0, Automata::Alive);
+        hashlife.toggle(1, 0);
+        assert_eq!(hashlife.get(1, 0), Some(Automata::Alive));
+        hashlife.toggle(1, 0);
+        assert_eq!(hashlife.get(1, 0), Some(Automata::Dead));
+        hashlife.toggle(1, 0);
+
+        // The blinker turns vertical.
+        hashlife.next_generation();
+        assert_eq!(hashlife.get(0, 1), Some(Automata::Alive));
+        assert_eq!(hashlife.get(0, 0), Some(Automata::Alive));
+        assert_eq!(hashlife.get(0, -1), Some(Automata::Alive));
+        assert_eq!(hashlife.get(-1, 0), Some(Automata::Dead));
+
+        // The universe grows to fit a far away cell.
+        hashlife.set(1000, -3000, Automata::Alive);
+        assert_eq!(hashlife.get(1000, -3000), Some(Automata::Alive));
+        assert_eq!(hashlife.get(0, 1), Some(Automata::Alive));
+        assert_eq!(hashlife.max_level(), 13);
+    }
+
+    #[test]
+    fn set_wraps_around_torus() {
+        let mut hashlife = Hashlife::from_array(vec![0; 64], 8, 8, Edge::Torus);
+        hashlife.set(9, -12, Automata::Alive);
+        assert_eq!(hashlife.get(1, -4), Some(Automata::Alive));
+        assert_eq!(hashlife.max_level(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_outside_truncated_universe() {
+        let mut hashlife = Hashlife::from_array(vec![0; 64], 8, 8, Edge::Truncate);
+        hashlife.set(4, 0, Automata::Alive);
+    }
+
+    #[test]
+    fn get_outside_of_the_tree() {
+        for edge in [Edge::Infinite, Edge::Truncate] {
+            let hashlife = Hashlife::from_array(vec![1; 16], 4, 4, edge);
+            assert_eq!(hashlife.get(1, 1), Some(Automata::Alive));
+            assert_eq!(hashlife.get(2, 1), Some(Automata::Dead));
+            assert_eq!(hashlife.get(-3, 0), Some(Automata::Dead));
+            assert_eq!(hashlife.get(isize::MAX, isize::MIN), Some(Automata::Dead));
+        }
+        let single = Hashlife::from_array(vec![1], 1, 1, Edge::Infinite);
+        assert_eq!(single.get(0, 0), Some(Automata::Alive));
+        assert_eq!(single.get(-1, 0), Some(Automata::Dead));
+        assert_eq!(single.get(0, 1), Some(Automata::Dead));
+    }
+
+    #[test]
+    fn get_wraps_around_torus() {
+        let hashlife = Hashlife::from_array(soup(), 16, 16, Edge::Torus);
+        for (x, y) in [(-8, 7), (7, -8), (0, 0), (3, -5)] {
+            let cell = hashlife.get(x, y);
+            assert_eq!(hashlife.get(x + 16, y), cell);
+            assert_eq!(hashlife.get(x - 32, y + 48), cell);
+            assert_eq!(hashlife.get(x + 16 * 1000, y - 16 * 1000), cell);
+        }
+        assert_eq!(hashlife.get(isize::MAX, isize::MIN), hashlife.get(-1, 0));
+        let single = Hashlife::from_array(vec![1], 1, 1, Edge::Torus);
+        assert_eq!(single.get(-5, 12), Some(Automata::Alive));
+    }
+
+    #[test]
+    fn get_region_matches_get() {
+        for edge in [Edge::Infinite, Edge::Torus] {
+            let hashlife = Hashlife::from_array(soup(), 16, 16, edge);
+            let area = BoundingBox::from(21, -30, -13, 40);
+            let region = hashlife.get_region(&area);
+            for x in area.left()..=area.right() {
+                for y in area.bottom()..=area.top() {
+                    assert_eq!(Some(region[area.index(x, y)]), hashlife.get(x, y), "{:?} at ({}, {})", edge, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn torus_repeats_cells_over_any_rectangle() {
+        let mut hashlife = Hashlife::from_array(vec![0; 64], 8, 8, Edge::Torus);
+        hashlife.set(3, 0, Automata::Alive);
+        hashlife.set(-4, -3, Automata::Alive);
+        let areas = [
+            BoundingBox::from(5, -5, isize::MAX - 20, isize::MAX),
+            BoundingBox::from(isize::MIN + 9, isize::MIN, isize::MIN, isize::MIN + 30),
+            BoundingBox::from(2, -1, -6, -2),
+        ];
+        for area in &areas {
+            let mut cells = Vec::new();
+            hashlife.for_each_live_in(area, |x, y| cells.push((x, y)));
+            cells.sort_unstable();
+            let mut expected = Vec::new();
+            for x in area.left()..=area.right() {
+                for y in area.bottom()..=area.top() {
+                    if hashlife.get(x, y) == Some(Automata::Alive) {
+                        expected.push((x, y));
+                    }
+                }
+            }
+            assert_eq!(cells, expected, "{:?}", area);
+        }
+        // A row of the whole plane without a living cell is read at once.
+        let mut count = 0;
+        hashlife.for_each_live_in(&BoundingBox::from(1, 1, isize::MIN, isize::MAX), |_, _| count += 1);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn try_from_array_rejects_bad_buffers() {
+        assert_eq!(
+            Hashlife::try_from_array(vec![0; 5], 2, 3, Edge::Infinite).err(),
+            Some(HashlifeError::BufferSize { expected: 6, actual: 5 })
+        );
+        assert_eq!(Hashlife::try_from_array(vec![0, 1, 2, 0], 2, 2, Edge::Torus).err(), Some(HashlifeError::InvalidState(2)));
+        assert_eq!(Hashlife::try_from_array(vec![], 0, 4, Edge::Truncate).err(), Some(HashlifeError::Empty));
+        assert_eq!(Hashlife::try_from_array(vec![], usize::MAX, 2, Edge::Infinite).err(), Some(HashlifeError::TooLarge));
+        let hashlife = Hashlife::try_from_array(vec![1, 0, 0, 1], 2, 2, Edge::Infinite).unwrap();
+        assert_eq!(hashlife.get(-1, 0), Some(Automata::Alive));
+    }
+
+    #[test]
+    fn try_queries_fail_instead_of_panicking() {
+        let mut hashlife = Hashlife::from_array(vec![0; 64], 8, 8, Edge::Truncate);
+        assert_eq!(hashlife.try_set(3, -4, Automata::Alive), Ok(()));
+        assert_eq!(hashlife.get(3, -4), Some(Automata::Alive));
+        assert_eq!(hashlife.try_set(4, 0, Automata::Alive), Err(HashlifeError::OutOfBounds { x: 4, y: 0 }));
+        assert_eq!(hashlife.try_toggle(0, -5), Err(HashlifeError::OutOfBounds { x: 0, y: -5 }));
+        assert_eq!(hashlife.try_get_region(&BoundingBox::from(0, 1, 0, 0)), Err(HashlifeError::InvalidRectangle));
+        let plane = BoundingBox::from(isize::MAX, isize::MIN, isize::MIN, isize::MAX);
+        assert_eq!(hashlife.try_get_region(&plane), Err(HashlifeError::TooLarge));
+        assert_eq!((BoundingBox::from(0, 1, 5, 4).width(), BoundingBox::from(0, 1, 5, 4).height()), (0, 0));
+
+        // An infinite universe reaches 2^61 cells from the origin, a torus
+        // wraps any cell.
+        let mut infinite = Hashlife::from_array(vec![0; 4], 2, 2, Edge::Infinite);
+        assert_eq!(infinite.try_set(isize::MAX, 0, Automata::Alive), Err(HashlifeError::OutOfBounds { x: isize::MAX, y: 0 }));
+        assert_eq!(infinite.try_toggle(0, -(1 << 61) - 1), Err(HashlifeError::OutOfBounds { x: 0, y: -(1 << 61) - 1 }));
+        assert_eq!(infinite.population(), 0);
+        assert_eq!(infinite.try_set((1 << 61) - 1, -(1 << 61), Automata::Alive), Ok(()));
+        assert_eq!(infinite.get((1 << 61) - 1, -(1 << 61)), Some(Automata::Alive));
+        let mut torus = Hashlife::from_array(vec![0; 4], 2, 2, Edge::Torus);
+        assert_eq!(torus.try_set(isize::MAX, isize::MIN, Automata::Alive), Ok(()));
+        assert_eq!(torus.population(), 1);
+    }
+
+    #[test]
+    fn try_advance_respects_limits() {
+        let mut hashlife = Hashlife::from_array(soup(), 16, 16, Edge::Infinite);
+        hashlife.advance(10);
+        assert_eq!(
+            hashlife.try_goto_generation(5),
+            Err(HashlifeError::GenerationInPast { current: 10, requested: 5 })
+        );
+        assert_eq!(hashlife.try_advance(usize::MAX), Err(HashlifeError::GenerationOverflow));
+        assert_eq!(hashlife.try_step_pow2(usize::BITS as usize), Err(HashlifeError::GenerationOverflow));
+
+        // Not even the tree of the current generation fits in ten nodes.
+        let top = hashlife.top;
+        hashlife.set_cache_limits(CacheLimits { max_nodes: Some(10), max_bytes: None });
+        assert_eq!(hashlife.try_advance(5), Err(HashlifeError::CacheLimitExceeded));
+        assert_eq!(hashlife.get_generation(), 10);
+        assert_eq!(hashlife.top, top);
+        assert_eq!(hashlife.try_advance(0), Ok(()));
+        assert_eq!(hashlife.get_generation(), 10);
+
+        // The first step over the limit stops the advance, instead of each
+        // of the 2^40 generations of a truncated universe collecting.
+        let mut truncated = Hashlife::from_array(soup(), 16, 16, Edge::Truncate);
+        truncated.set_cache_limits(CacheLimits { max_nodes: Some(3), max_bytes: None });
+        assert_eq!(truncated.try_step_pow2(40), Err(HashlifeError::CacheLimitExceeded));
+        assert_eq!(truncated.get_generation(), 0);
+
+        hashlife.set_cache_limits(CacheLimits::default());
+        assert_eq!(hashlife.try_goto_generation(16), Ok(()));
+        assert_eq!(hashlife.get_generation(), 16);
+
+        // The nodes of the generation before the failed call may have been
+        // collected, drawing a diff must not visit them.
+        let acorn = vec![
+            0,1,0,0,0,0,0,
+            0,0,0,1,0,0,0,
+            1,1,0,0,1,1,1,
+        ];
+        let mut hashlife = Hashlife::from_array(acorn, 7, 3, Edge::Infinite);
+        hashlife.advance(100);
+        hashlife.advance(100);
+        hashlife.set_cache_limits(CacheLimits { max_nodes: Some(200), max_bytes: None });
+        assert_eq!(hashlife.try_advance(1000), Err(HashlifeError::CacheLimitExceeded));
+        hashlife.set_cache_limits(CacheLimits::default());
+        for x in 0..4 {
+            hashlife.set(x, 40, Automata::Alive);
+        }
+        let viewport = BoundingBox::from(63, -64, -64, 63);
+        let mut buffer = vec![0; viewport.width() * viewport.height()];
+        hashlife.draw_diff_to_viewport_array(&mut buffer, viewport);
+    }
+
+    #[test]
+    fn tiny_universes_advance() {
+        // Under S8 only a cell with eight living neighbours survives, which
+        // a torus of one or two cells across gives every living cell.
+        let rule = Rule::new(&[], &[8]);
+        for &(side, edge, survives) in &[
+            (1, Edge::Infinite, false),
+            (1, Edge::Torus, true),
+            (1, Edge::Truncate, false),
+            (2, Edge::Infinite, false),
+            (2, Edge::Torus, true),
+            (2, Edge::Truncate, false),
+        ] {
+            let cells = vec![1; side * side];
+            let mut hashlife = Hashlife::from_array_with_rule(cells, side, side, edge, rule.clone());
+            assert_eq!(hashlife.try_step_pow2(0), Ok(()), "{}x{} {:?}", side, side, edge);
+            assert_eq!(hashlife.try_advance(1001), Ok(()), "{}x{} {:?}", side, side, edge);
+            assert_eq!(hashlife.try_step_pow2(5), Ok(()), "{}x{} {:?}", side, side, edge);
+            assert_eq!(hashlife.get_generation(), 1034);
+            let expected = if survives { side * side } else { 0 };
+            assert_eq!(hashlife.population(), expected as u128, "{}x{} {:?}", side, side, edge);
+            if edge != Edge::Infinite {
+                assert_eq!(hashlife.as_vector().len(), side * side);
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+//! The Life 1.05 format. After the `#Life 1.05` header come `#D` comment
+//! lines, `#N` for Conway's rule or `#R <survival>/<birth>` for another one,
+//! then blocks of cells. Each block starts with `#P <x> <y>`, the position of
+//! its top left cell with `y` counting rows down, followed by rows of `.` for
+//! a dead cell and `*` for a living one.
+
+use std::io::{self, Write};
+
+use crate::format::{pattern_at, rows_down, written_area, MAX_SIDE};
+use crate::rle::WriteOptions;
+use crate::{BoundingBox, Hashlife, HashlifeError, Metadata, Pattern, Rule};
+
+/// Blocks are written at most this many cells wide.
+const BLOCK_WIDTH: usize = 80;
+
+/// Read a pattern from a Life 1.05 file, each block at its position with `y`
+/// turned up. `#D Name:` and `#D Author:` lines fill in the name and author.
+pub fn read(source: &str) -> Result<Pattern, HashlifeError> {
+    let mut metadata = Metadata::default();
+    let mut cells = Vec::new();
+    // The position of the next row of cells.
+    let (mut left, mut row) = (0isize, 0isize);
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix("#D") {
+            let comment = comment.trim();
+            if let Some(name) = comment.strip_prefix("Name:") {
+                metadata.name = Some(name.trim().to_string());
+            } else if let Some(author) = comment.strip_prefix("Author:") {
+                metadata.author = Some(author.trim().to_string());
+            } else {
+                metadata.comments.push(comment.to_string());
+            }
+        } else if line.starts_with("#N") {
+            metadata.rule = Some(Rule::life());
+        } else if let Some(rule) = line.strip_prefix("#R") {
+            let rule = rule.trim().parse::<Rule>().map_err(|err| HashlifeError::parse(number, 4, &err.to_string()))?;
+            metadata.rule = Some(rule);
+        } else if let Some(position) = line.strip_prefix("#P") {
+            let coordinates = position.split_whitespace().map(|v| v.parse::<isize>()).collect::<Vec<_>>();
+            match coordinates[..] {
+                [Ok(x), Ok(y)] if x.unsigned_abs() <= MAX_SIDE && y.unsigned_abs() <= MAX_SIDE => {
+                    left = x;
+                    row = y;
+                },
+                _ => return Err(HashlifeError::parse(number, 4, "expected a position as `<x> <y>`")),
+            }
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else {
+            for (col, c) in line.chars().enumerate() {
+                match c {
+                    '*' => cells.push((left + col as isize, row)),
+                    '.' => (),
+                    c => return Err(HashlifeError::parse(number, col + 1, &format!("unexpected character `{}`", c))),
+                }
+            }
+            if row.unsigned_abs() >= MAX_SIDE {
+                return Err(HashlifeError::parse(number, 1, "the block is too far from the origin"));
+            }
+            row += 1;
+        }
+    }
+    Ok(pattern_at(&cells, metadata))
+}
+
+/// Write the cells of a universe inside a rectangle as a Life 1.05 file, in
+/// blocks of whole rows at most 80 cells wide. Without a rectangle the
+/// smallest one holding every living cell is written.
+pub fn write<W: Write>(hashlife: &Hashlife, area: Option<&BoundingBox>, writer: &mut W, options: &WriteOptions) -> io::Result<()> {
+    writeln!(writer, "#Life 1.05")?;
+    if let Some(name) = &options.name {
+        writeln!(writer, "#D Name: {}", name)?;
+    }
+    if let Some(author) = &options.author {
+        writeln!(writer, "#D Author: {}", author)?;
+    }
+    for comment in &options.comments {
+        writeln!(writer, "#D {}", comment)?;
+    }
+    let rule = hashlife.get_rule();
+    if *rule == Rule::life() {
+        writeln!(writer, "#N")?;
+    } else {
+        let digits = |counts: &[usize]| counts.iter().map(|n| n.to_string()).collect::<String>();
+        writeln!(writer, "#R {}/{}", digits(rule.survival()), digits(rule.birth()))?;
+    }
+    let area = match written_area(hashlife, area)? {
+        Some(area) => area,
+        None => return Ok(()),
+    };
+    let cells = rows_down(hashlife, &area);
+    let mut left = area.left();
+    while left <= area.right() {
+        let width = (area.right() - left + 1).min(BLOCK_WIDTH as isize);
+        writeln!(writer, "#P {} {}", left, -area.top())?;
+        let mut block = cells.iter().filter(|&&(_, x)| x >= left && x < left + width).peekable();
+        for y in -area.top()..=-area.bottom() {
+            let mut row = vec![b'.'; width as usize];
+            while let Some((_, x)) = block.next_if(|&&(row, _)| row == y) {
+                row[(x - left) as usize] = b'*';
+            }
+            let end = row.iter().rposition(|&c| c == b'*').map_or(1, |last| last + 1);
+            writer.write_all(&row[..end])?;
+            writeln!(writer)?;
+        }
+        left += width;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Automata, Edge};
+
+    #[test]
+    fn read_blocks_and_rule() {
+        let source = "#Life 1.05\n#D Name: Two blocks\n#D Far apart.\n#R 23/36\n#P -2 -1\n**\n*.*\n#P 10 5\n.*\n";
+        let pattern = read(source).unwrap();
+        let metadata = pattern.metadata();
+        assert_eq!(metadata.name.as_deref(), Some("Two blocks"));
+        assert_eq!(metadata.comments, vec!["Far apart."]);
+        assert_eq!(metadata.rule, Some("B36/S23".parse().unwrap()));
+        assert_eq!(*pattern.bounds(), BoundingBox::from(1, -5, -2, 11));
+        for &(x, y) in &[(-2, 1), (-1, 1), (-2, 0), (0, 0), (11, -5)] {
+            assert_eq!(pattern.get(x, y), Automata::Alive, "({}, {})", x, y);
+        }
+        assert_eq!(pattern.get(10, -5), Automata::Dead);
+
+        assert_eq!(read("#Life 1.05\n#N\n.*o\n").err(), Some(HashlifeError::parse(3, 3, "unexpected character `o`")));
+        assert_eq!(read("#Life 1.05\n#P 1\n").err(), Some(HashlifeError::parse(2, 4, "expected a position as `<x> <y>`")));
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let mut hashlife = Hashlife::from_array(vec![0; 256 * 256], 256, 256, Edge::Infinite);
+        for &(x, y) in &[(-90, 10), (-89, 9), (0, 10), (85, 8)] {
+            hashlife.set(x, y, Automata::Alive);
+        }
+        let mut out = Vec::new();
+        write(&hashlife, None, &mut out, &WriteOptions { name: Some("Spread".to_string()), ..WriteOptions::default() }).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let mut expected = "#Life 1.05\n#D Name: Spread\n#N\n#P -90 -10\n*\n.*\n.\n#P -10 -10\n".to_string();
+        expected.push_str(&format!("{}*\n.\n.\n#P 70 -10\n.\n.\n{}*\n", ".".repeat(10), ".".repeat(15)));
+        assert_eq!(text, expected);
+
+        let pattern = read(&text).unwrap();
+        assert_eq!(pattern.bounds(), &hashlife.live_bounds().unwrap());
+        assert_eq!(pattern.metadata().rule, Some(Rule::life()));
+        for &(x, y) in &[(-90, 10), (-89, 9), (0, 10), (85, 8)] {
+            assert_eq!(pattern.get(x, y), Automata::Alive, "({}, {})", x, y);
+        }
+    }
+}
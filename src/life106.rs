@@ -0,0 +1,116 @@
+//! The Life 1.06 format, a `#Life 1.06` header and `#D` comment lines
+//! followed by the living cells as `x y` lines, with `y` counting rows down.
+
+use std::io::{self, Write};
+
+use crate::format::{pattern_at, rows_down, written_area, MAX_SIDE};
+use crate::rle::WriteOptions;
+use crate::{BoundingBox, Hashlife, HashlifeError, Metadata, Pattern};
+
+/// Read a pattern from a Life 1.06 file, each cell at its coordinates with
+/// `y` turned up. `#D Name:` and `#D Author:` lines fill in the name and
+/// author, other `#D` lines are kept as comments and other `#` lines are
+/// skipped.
+pub fn read(source: &str) -> Result<Pattern, HashlifeError> {
+    let mut metadata = Metadata::default();
+    let mut cells = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix("#D") {
+            let comment = comment.trim();
+            if let Some(name) = comment.strip_prefix("Name:") {
+                metadata.name = Some(name.trim().to_string());
+            } else if let Some(author) = comment.strip_prefix("Author:") {
+                metadata.author = Some(author.trim().to_string());
+            } else {
+                metadata.comments.push(comment.to_string());
+            }
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: &str| HashlifeError::parse(index + 1, 1, reason);
+        let fields = line.split_whitespace().map(|field| field.parse::<isize>()).collect::<Vec<_>>();
+        let (x, y) = match fields[..] {
+            [Ok(x), Ok(y)] => (x, y),
+            _ => return Err(error("expected `<x> <y>`")),
+        };
+        if x.unsigned_abs() > MAX_SIDE || y.unsigned_abs() > MAX_SIDE {
+            return Err(error("the cell is too far from the origin"));
+        }
+        cells.push((x, y));
+    }
+    Ok(pattern_at(&cells, metadata))
+}
+
+/// Write the living cells of a universe inside a rectangle as a Life 1.06
+/// file, row by row from the top, after the name, author and comments as
+/// `#D` lines. Without a rectangle every living cell is written. The cells
+/// are always at their own coordinates, so `position` has no effect.
+pub fn write<W: Write>(hashlife: &Hashlife, area: Option<&BoundingBox>, writer: &mut W, options: &WriteOptions) -> io::Result<()> {
+    writeln!(writer, "#Life 1.06")?;
+    if let Some(name) = &options.name {
+        writeln!(writer, "#D Name: {}", name)?;
+    }
+    if let Some(author) = &options.author {
+        writeln!(writer, "#D Author: {}", author)?;
+    }
+    for comment in &options.comments {
+        writeln!(writer, "#D {}", comment)?;
+    }
+    if let Some(area) = written_area(hashlife, area)? {
+        for (y, x) in rows_down(hashlife, &area) {
+            writeln!(writer, "{} {}", x, y)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Automata, Edge};
+
+    #[test]
+    fn read_keeps_coordinates() {
+        let pattern = read("#Life 1.06\n#D A loose pair\n\n-3 10\n 4  -2 \n").unwrap();
+        assert_eq!(pattern.metadata().comments, vec!["A loose pair"]);
+        assert_eq!(*pattern.bounds(), BoundingBox::from(2, -10, -3, 4));
+        assert_eq!(pattern.get(-3, -10), Automata::Alive);
+        assert_eq!(pattern.get(4, 2), Automata::Alive);
+        assert_eq!(pattern.get(4, -10), Automata::Dead);
+
+        assert_eq!(read("#Life 1.06\n0 0\n1\n").err(), Some(HashlifeError::parse(3, 1, "expected `<x> <y>`")));
+        assert!(read("#Life 1.06\n0 2000000000000000000\n").is_err());
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let mut hashlife = Hashlife::from_array(vec![0; 64], 8, 8, Edge::Infinite);
+        for &(x, y) in &[(-4, 3), (2, 3), (0, -4)] {
+            hashlife.set(x, y, Automata::Alive);
+        }
+        let options = WriteOptions {
+            name: Some("Trio".to_string()),
+            author: Some("Someone".to_string()),
+            comments: vec!["Three loose cells".to_string()],
+            position: true,
+        };
+        let mut out = Vec::new();
+        write(&hashlife, None, &mut out, &options).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "#Life 1.06\n#D Name: Trio\n#D Author: Someone\n#D Three loose cells\n-4 -3\n2 -3\n0 4\n");
+
+        let pattern = read(&text).unwrap();
+        assert_eq!(pattern.bounds(), &hashlife.live_bounds().unwrap());
+        assert_eq!(pattern.get(0, -4), Automata::Alive);
+        let metadata = pattern.metadata();
+        assert_eq!((metadata.name.as_deref(), metadata.author.as_deref()), (Some("Trio"), Some("Someone")));
+        assert_eq!(metadata.comments, vec!["Three loose cells"]);
+
+        let mut out = Vec::new();
+        write(&hashlife, Some(&BoundingBox::from(0, -4, -4, 4)), &mut out, &WriteOptions::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "#Life 1.06\n0 4\n");
+    }
+}
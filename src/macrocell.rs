@@ -0,0 +1,241 @@
+//! The macrocell format, the quadtree of a universe written one node per
+//! line so shared nodes are stored once.
+//!
+//! A file starts with `[M2]`, then `#` lines such as `#R B3/S23` for the rule
+//! and `#G 100` for the generation. An 8x8 leaf is a line of rows ending in
+//! `$`, with `.` for a dead cell and `*` for a living one. A larger node is
+//! `<level> <nw> <ne> <sw> <se>`, its children referring to earlier node
+//! lines counted from 1, or 0 for an empty child. The last node is the top of
+//! the tree.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::store::{Store, LEAF_LEVEL, MAX_LEVEL};
+use crate::{top_corner, Content, Hashlife, HashlifeError, NodeId, Rule};
+
+/// Read a universe from a macrocell file. The tree is joined node by node
+/// without expanding the cells, the last node becomes the top of an infinite
+/// universe.
+pub fn read(source: &str) -> Result<Hashlife, HashlifeError> {
+    let mut hashlife = Hashlife::new();
+    // Every node line in order, after a placeholder for the empty child.
+    let mut nodes = vec![NodeId::DEAD];
+    let mut lines = source.lines().enumerate().map(|(index, line)| (index + 1, line.trim()));
+
+    match lines.find(|(_, line)| !line.is_empty()) {
+        Some((_, line)) if line.starts_with("[M2]") => (),
+        Some((number, _)) => return Err(HashlifeError::parse(number, 1, "expected the `[M2]` header")),
+        None => return Err(HashlifeError::parse(1, 1, "expected the `[M2]` header")),
+    }
+
+    for (number, line) in lines {
+        if let Some(rule) = line.strip_prefix("#R") {
+            let rule = rule.trim().parse::<Rule>().map_err(|err| HashlifeError::parse(number, 4, &err.to_string()))?;
+            hashlife.set_rule(rule);
+        } else if let Some(generation) = line.strip_prefix("#G") {
+            hashlife.gen = generation.trim().parse().map_err(|_| HashlifeError::parse(number, 4, "expected a generation"))?;
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else if line.starts_with(|c: char| c.is_ascii_digit()) {
+            let fields = line.split_whitespace().map(|field| field.parse::<usize>()).collect::<Vec<_>>();
+            let (level, children) = match fields[..] {
+                [Ok(level), Ok(nw), Ok(ne), Ok(sw), Ok(se)] => (level, [nw, ne, sw, se]),
+                _ => return Err(HashlifeError::parse(number, 1, "expected `<level> <nw> <ne> <sw> <se>`")),
+            };
+            if level <= LEAF_LEVEL || level > MAX_LEVEL {
+                return Err(HashlifeError::parse(number, 1, "the level is out of range"));
+            }
+            let mut ids = [NodeId::DEAD; 4];
+            for (id, &child) in ids.iter_mut().zip(&children) {
+                *id = match child {
+                    0 => hashlife.empty(level - 1),
+                    _ => match nodes.get(child) {
+                        Some(&node) if hashlife.store.get(node).level == level - 1 => node,
+                        _ => return Err(HashlifeError::parse(number, 1, &format!("{} is not an earlier node of level {}", child, level - 1))),
+                    },
+                };
+            }
+            let [nw, ne, sw, se] = ids;
+            nodes.push(hashlife.join(nw, ne, sw, se));
+        } else {
+            nodes.push(hashlife.store.leaf(LEAF_LEVEL, leaf(line, number)?));
+        }
+    }
+
+    let top = match nodes.last() {
+        Some(&top) if nodes.len() > 1 => top,
+        _ => hashlife.empty(0),
+    };
+    hashlife.top = Some(top);
+    Ok(hashlife)
+}
+
+/// Read the bitboard of a leaf line.
+fn leaf(line: &str, number: usize) -> Result<u64, HashlifeError> {
+    let (mut bits, mut row, mut col) = (0u64, 0, 0);
+    for (column, c) in line.chars().enumerate() {
+        let error = |reason: &str| HashlifeError::parse(number, column + 1, reason);
+        match c {
+            '.' | '*' if row < 8 && col < 8 => {
+                bits |= ((c == '*') as u64) << (row * 8 + col);
+                col += 1;
+            },
+            '.' | '*' => return Err(error("a leaf is 8 cells wide")),
+            '$' if row < 8 => {
+                row += 1;
+                col = 0;
+            },
+            '$' => return Err(error("a leaf is 8 cells high")),
+            c => return Err(error(&format!("unexpected character `{}`", c))),
+        }
+    }
+    Ok(bits)
+}
+
+/// Write the tree of a universe as a macrocell file, with its rule and
+/// generation. Each distinct node is written once, children first. Trees
+/// smaller than a leaf are written as a leaf around the same cells.
+pub fn write<W: Write>(hashlife: &Hashlife, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "[M2]")?;
+    writeln!(writer, "#R {}", hashlife.get_rule())?;
+    writeln!(writer, "#G {}", hashlife.get_generation())?;
+    let top = match hashlife.top {
+        Some(top) => top,
+        None => return Ok(()),
+    };
+    let n = hashlife.store.get(top);
+    if n.population == 0 {
+        return Ok(());
+    }
+    if n.level < LEAF_LEVEL {
+        let (left, top) = top_corner(LEAF_LEVEL);
+        let bits = hashlife
+            .live_cells()
+            .fold(0u64, |bits, (x, y)| bits | 1 << ((top - y) * 8 + x - left));
+        return writeln!(writer, "{}", leaf_line(bits));
+    }
+    write_node(&hashlife.store, top, &mut HashMap::new(), writer)?;
+    Ok(())
+}
+
+/// Write a node after its children unless it was written already, returning
+/// its line number among the nodes.
+fn write_node<W: Write>(store: &Store, node: NodeId, written: &mut HashMap<NodeId, usize>, writer: &mut W) -> io::Result<usize> {
+    let n = store.get(node);
+    if n.population == 0 {
+        return Ok(0);
+    }
+    if let Some(&index) = written.get(&node) {
+        return Ok(index);
+    }
+    match n.content {
+        Content::Leaf(bits) => writeln!(writer, "{}", leaf_line(bits))?,
+        Content::Branch(c) => {
+            let nw = write_node(store, c.nw, written, writer)?;
+            let ne = write_node(store, c.ne, written, writer)?;
+            let sw = write_node(store, c.sw, written, writer)?;
+            let se = write_node(store, c.se, written, writer)?;
+            writeln!(writer, "{} {} {} {} {}", n.level, nw, ne, sw, se)?;
+        },
+    }
+    let index = written.len() + 1;
+    written.insert(node, index);
+    Ok(index)
+}
+
+/// The rows of a leaf without their trailing dead cells, and without the
+/// trailing empty rows.
+fn leaf_line(bits: u64) -> String {
+    let mut rows = (0..8)
+        .map(|row| {
+            let cells = (0..8).map(|col| if bits >> (row * 8 + col) & 1 == 1 { '*' } else { '.' }).collect::<String>();
+            cells.trim_end_matches('.').to_string()
+        })
+        .collect::<Vec<_>>();
+    while rows.last().is_some_and(|row| row.is_empty()) {
+        rows.pop();
+    }
+    rows.iter().map(|row| format!("{}$", row)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Automata, Edge};
+
+    fn written(hashlife: &Hashlife) -> String {
+        let mut out = Vec::new();
+        write(hashlife, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn alive(hashlife: &Hashlife) -> Vec<(isize, isize)> {
+        let mut cells = hashlife.live_cells().collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn write_shares_nodes() {
+        let mut hashlife = Hashlife::from_array(vec![0; 16 * 16], 16, 16, Edge::Infinite);
+        // The same glider in two quadrants.
+        for &(dx, dy) in &[(-8, 0), (0, -8)] {
+            for &(x, y) in &[(1, 7), (2, 6), (0, 5), (1, 5), (2, 5)] {
+                hashlife.set(x + dx, y + dy, Automata::Alive);
+            }
+        }
+        hashlife.set_rule("B36/S23".parse().unwrap());
+        hashlife.gen = 12;
+        assert_eq!(written(&hashlife), "[M2]\n#R B36/S23\n#G 12\n.*$..*$***$\n4 1 0 0 1\n");
+    }
+
+    #[test]
+    fn read_joins_nodes() {
+        let source = "[M2] (golly 4.2)\n#R 23/36\n#G 1000\n.*$..*$***$\n$$$$$$$*$\n4 1 0 0 2\n5 3 3 0 3\n";
+        let hashlife = read(source).unwrap();
+        assert_eq!(hashlife.get_rule(), &"B36/S23".parse().unwrap());
+        assert_eq!(hashlife.get_generation(), 1000);
+        assert_eq!(hashlife.max_level(), 5);
+        assert_eq!(hashlife.population(), 18);
+        // The glider at the top left of the north west level 4 node.
+        assert_eq!(hashlife.get(-16, 15), Some(Automata::Dead));
+        assert_eq!(hashlife.get(-15, 15), Some(Automata::Alive));
+        assert_eq!(hashlife.get(-15, 14), Some(Automata::Dead));
+        assert_eq!(hashlife.get(-14, 14), Some(Automata::Alive));
+    }
+
+    #[test]
+    fn round_trips() {
+        let glider = vec![
+            0,1,0,
+            0,0,1,
+            1,1,1,
+        ];
+        let mut hashlife = Hashlife::from_array(glider, 3, 3, Edge::Infinite);
+        hashlife.advance(1000);
+        let copy = read(&written(&hashlife)).unwrap();
+        assert_eq!(alive(&copy), alive(&hashlife));
+        assert_eq!(copy.get_generation(), 1000);
+
+        let small = Hashlife::from_array(vec![1, 0, 1, 1], 2, 2, Edge::Torus);
+        assert_eq!(alive(&read(&written(&small)).unwrap()), alive(&small));
+        let empty = read(&written(&Hashlife::from_array(vec![0; 4], 2, 2, Edge::Infinite))).unwrap();
+        assert_eq!(empty.population(), 0);
+    }
+
+    #[test]
+    fn read_reports_positions() {
+        let position = |source| match read(source) {
+            Err(HashlifeError::Parse { line, column, .. }) => (line, column),
+            other => panic!("expected a parse error, got {:?}", other.map(|hashlife| hashlife.population())),
+        };
+        assert_eq!(position("#R B3/S23\n"), (1, 1));
+        assert_eq!(position("[M2]\n.........*$"), (2, 9));
+        assert_eq!(position("[M2]\n$$$$$$$$$"), (2, 9));
+        assert_eq!(position("[M2]\n.*x"), (2, 3));
+        assert_eq!(position("[M2]\n*$\n5 1 0 0 0"), (3, 1));
+        assert_eq!(position("[M2]\n*$\n4 2 0 0 0"), (3, 1));
+        assert_eq!(position("[M2]\n#R B9/S\n"), (2, 4));
+    }
+}
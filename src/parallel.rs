@@ -0,0 +1,406 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+
+use crate::cache::Cache;
+use crate::rule::Transitions;
+use crate::step::{self, Nonants, Serial, Tables};
+use crate::store::{Nodes, Store};
+use crate::{Children, Content, Node, NodeId};
+
+/// Nodes below this level are stepped on a single thread, spawning threads
+/// for them costs more than it saves.
+const PARALLEL_LEVEL: usize = 7;
+
+/// Number of independently locked parts the new nodes and results are
+/// spread over.
+const SHARDS: usize = 64;
+
+/// Nodes created during a parallel step, kept apart from the arena so the
+/// arena can be read without locking. A node lives in the shard its hash
+/// picks, so it is created at most once. The `i`th node of shard `s` gets
+/// the id `first + i * SHARDS + s`, past every id of the arena.
+struct NewNodes {
+    first: usize,
+    shards: Vec<RwLock<Shard>>,
+}
+
+#[derive(Default)]
+struct Shard {
+    nodes: Vec<Node>,
+    index: HashMap<Node, NodeId>,
+}
+
+impl NewNodes {
+    fn new(first: usize) -> Self {
+        Self { first, shards: (0..SHARDS).map(|_| RwLock::default()).collect() }
+    }
+
+    fn get(&self, id: NodeId) -> Node {
+        let offset = id.index() - self.first;
+        read(&self.shards[offset % SHARDS]).nodes[offset / SHARDS]
+    }
+
+    fn insert(&self, node: Node) -> NodeId {
+        let mut hasher = DefaultHasher::new();
+        node.hash(&mut hasher);
+        let shard = hasher.finish() as usize % SHARDS;
+        if let Some(&id) = read(&self.shards[shard]).index.get(&node) {
+            return id;
+        }
+        let mut nodes = write(&self.shards[shard]);
+        // Another thread may have created the node in the meantime.
+        if let Some(&id) = nodes.index.get(&node) {
+            return id;
+        }
+        let id = u32::try_from(self.first + nodes.nodes.len() * SHARDS + shard).expect("node arena is full");
+        nodes.nodes.push(node);
+        nodes.index.insert(node, NodeId(id));
+        NodeId(id)
+    }
+
+    fn into_nodes(self) -> impl Iterator<Item = (NodeId, Node)> {
+        self.shards.into_iter().flat_map(|shard| {
+            let shard = shard.into_inner().expect("a worker thread panicked");
+            shard.index.into_iter().map(|(node, id)| (id, node))
+        })
+    }
+}
+
+/// Results recorded during a parallel step, on top of the memo table of the
+/// `Hashlife` which is only read.
+struct NewResults<'a> {
+    old: &'a HashMap<NodeId, NodeId>,
+    shards: Vec<RwLock<HashMap<NodeId, NodeId>>>,
+}
+
+impl<'a> NewResults<'a> {
+    fn new(old: &'a HashMap<NodeId, NodeId>) -> Self {
+        Self { old, shards: (0..SHARDS).map(|_| RwLock::default()).collect() }
+    }
+
+    fn get(&self, node: NodeId) -> Option<NodeId> {
+        let shard = &self.shards[node.index() % SHARDS];
+        self.old.get(&node).copied().or_else(|| read(shard).get(&node).copied())
+    }
+
+    fn insert(&self, node: NodeId, result: NodeId) {
+        write(&self.shards[node.index() % SHARDS]).insert(node, result);
+    }
+
+    fn into_results(self) -> impl Iterator<Item = (NodeId, NodeId)> {
+        self.shards.into_iter().flat_map(|shard| shard.into_inner().expect("a worker thread panicked"))
+    }
+}
+
+/// The node table and memo tables of a `Hashlife`, shared by the worker
+/// threads of a parallel step. The arena and the memo tables are frozen for
+/// the step and read without locking, what the step adds is spread over
+/// shards with a lock each. The node table stays canonical, so the result is
+/// the very node the serial engine builds.
+struct Shared<'a> {
+    store: &'a Store,
+    nodes: NewNodes,
+    step: NewResults<'a>,
+    jump: NewResults<'a>,
+    step_exponent: usize,
+    transitions: &'a Transitions,
+    /// Threads that may still be started, so at most one runs per core.
+    spare_threads: AtomicUsize,
+}
+
+impl<'a> Shared<'a> {
+    fn new(store: &'a Store, cache: &'a Cache, transitions: &'a Transitions, threads: usize) -> Self {
+        Self {
+            store,
+            nodes: NewNodes::new(store.capacity()),
+            // `forked` makes sure the table of the exponent exists.
+            step: NewResults::new(&cache.step[&cache.step_exponent]),
+            jump: NewResults::new(&cache.jump),
+            step_exponent: cache.step_exponent,
+            transitions,
+            spare_threads: AtomicUsize::new(threads - 1),
+        }
+    }
+
+    /// Take one of the spare threads if there is any left.
+    fn claim_thread(&self) -> bool {
+        self.spare_threads.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1)).is_ok()
+    }
+
+    fn release_thread(&self) {
+        self.spare_threads.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn has_spare_thread(&self) -> bool {
+        self.spare_threads.load(Ordering::Acquire) > 0
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().expect("a worker thread panicked")
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().expect("a worker thread panicked")
+}
+
+/// A worker thread's view of the shared tables.
+#[derive(Clone, Copy)]
+struct Worker<'s, 'a>(&'s Shared<'a>);
+
+impl Worker<'_, '_> {
+    fn insert(&self, node: Node) -> NodeId {
+        match self.0.store.find(&node) {
+            Some(id) => id,
+            None => self.0.nodes.insert(node),
+        }
+    }
+}
+
+impl Nodes for Worker<'_, '_> {
+    fn node(&self, id: NodeId) -> Node {
+        if id.index() < self.0.nodes.first {
+            *self.0.store.get(id)
+        } else {
+            self.0.nodes.get(id)
+        }
+    }
+}
+
+impl Tables for Worker<'_, '_> {
+    fn join(&mut self, nw: NodeId, ne: NodeId, sw: NodeId, se: NodeId) -> NodeId {
+        let node = self.joined(Children { nw, ne, sw, se });
+        self.insert(node)
+    }
+
+    fn leaf(&mut self, level: usize, bits: u64) -> NodeId {
+        self.insert(Node::leaf(level, bits))
+    }
+
+    fn split(&mut self, node: NodeId) -> Children {
+        let n = self.node(node);
+        match n.content {
+            Content::Branch(children) => children,
+            Content::Leaf(_) => {
+                let [nw, ne, sw, se] = n.quadrants();
+                Children {
+                    nw: self.insert(nw),
+                    ne: self.insert(ne),
+                    sw: self.insert(sw),
+                    se: self.insert(se),
+                }
+            },
+        }
+    }
+
+    fn step_exponent(&self) -> usize {
+        self.0.step_exponent
+    }
+
+    fn transitions(&self) -> &Transitions {
+        self.0.transitions
+    }
+
+    fn cached_step(&self, node: NodeId) -> Option<NodeId> {
+        self.0.step.get(node)
+    }
+
+    fn cache_step(&mut self, node: NodeId, result: NodeId) {
+        self.0.step.insert(node, result);
+    }
+
+    fn cached_jump(&self, node: NodeId) -> Option<NodeId> {
+        self.0.jump.get(node)
+    }
+
+    fn cache_jump(&mut self, node: NodeId, result: NodeId) {
+        self.0.jump.insert(node, result);
+    }
+}
+
+/// Advance the centre of a node like `step::step`, stepping the independent
+/// nonants and quadrants of the top levels on their own threads, at most one
+/// per core. A single core skips the sharing altogether.
+pub(crate) fn step(store: &mut Store, cache: &mut Cache, transitions: &Transitions, node: NodeId) -> NodeId {
+    match thread::available_parallelism().map_or(1, |n| n.get()) {
+        1 => step::step(&mut Serial::new(store, cache, transitions), node),
+        threads => forked(store, cache, transitions, threads, |shared| fork_step(shared, node)),
+    }
+}
+
+/// Run a parallel step on up to `threads` threads, then add the nodes and
+/// results it created to the arena and the memo tables.
+fn forked<F>(store: &mut Store, cache: &mut Cache, transitions: &Transitions, threads: usize, f: F) -> NodeId
+where
+    F: FnOnce(&Shared) -> NodeId,
+{
+    let k = cache.step_exponent;
+    cache.step.entry(k).or_default();
+    let shared = Shared::new(store, cache, transitions, threads);
+    let result = f(&shared);
+    let Shared { nodes, step, jump, .. } = shared;
+    let (step, jump) = (step.into_results().collect::<Vec<_>>(), jump.into_results().collect::<Vec<_>>());
+    store.adopt(nodes.into_nodes());
+    cache.step.entry(k).or_default().extend(step);
+    cache.jump.extend(jump);
+    result
+}
+
+fn fork_step(shared: &Shared, node: NodeId) -> NodeId {
+    let mut worker = Worker(shared);
+    let level = worker.node(node).level;
+    if level <= shared.step_exponent + 2 {
+        return fork_jump(shared, node);
+    }
+    if level < PARALLEL_LEVEL || !shared.has_spare_thread() {
+        return step::step(&mut worker, node);
+    }
+    if let Some(result) = worker.cached_step(node) {
+        return result;
+    }
+    let Nonants { nw, ne, sw, se, n_, e_, s_, w_, c_ } = step::split_nonants(&mut worker, node);
+    let [nw, ne, sw, se, n_, e_, s_, w_, c_] = in_parallel(
+        shared,
+        [nw, ne, sw, se, n_, e_, s_, w_, c_],
+        |n| fork_step(shared, n),
+    );
+    let result = step::join_nonants(&mut worker, Nonants { nw, ne, sw, se, n_, e_, s_, w_, c_ });
+    worker.cache_step(node, result);
+    result
+}
+
+fn fork_jump(shared: &Shared, node: NodeId) -> NodeId {
+    let mut worker = Worker(shared);
+    if worker.node(node).level < PARALLEL_LEVEL || !shared.has_spare_thread() {
+        return step::jump(&mut worker, node);
+    }
+    if let Some(result) = worker.cached_jump(node) {
+        return result;
+    }
+    let Nonants { nw, ne, sw, se, n_, e_, s_, w_, c_ } = step::split_nonants(&mut worker, node);
+
+    // First half: the nonants are independent of each other.
+    let [nw, ne, sw, se, n_, e_, s_, w_, c_] = in_parallel(
+        shared,
+        [nw, ne, sw, se, n_, e_, s_, w_, c_],
+        |n| fork_jump(shared, n),
+    );
+
+    // Second half: so are the overlapping quadrants.
+    let quadrants = [
+        worker.join(nw, n_, w_, c_),
+        worker.join(n_, ne, c_, e_),
+        worker.join(w_, c_, sw, s_),
+        worker.join(c_, e_, s_, se),
+    ];
+    let [nw, ne, sw, se] = in_parallel(shared, quadrants, |n| fork_jump(shared, n));
+    let result = worker.join(nw, ne, sw, se);
+    worker.cache_jump(node, result);
+    result
+}
+
+/// Apply `f` to every node, on a thread of its own while spare threads are
+/// left and on the calling thread after that. A panic in a worker is resumed
+/// on the calling thread.
+fn in_parallel<F, const N: usize>(shared: &Shared, nodes: [NodeId; N], f: F) -> [NodeId; N]
+where
+    F: Fn(NodeId) -> NodeId + Sync,
+{
+    let f = &f;
+    thread::scope(|scope| {
+        let started = nodes.map(|node| {
+            if shared.claim_thread() {
+                Ok(scope.spawn(move || {
+                    let result = f(node);
+                    shared.release_thread();
+                    result
+                }))
+            } else {
+                Err(node)
+            }
+        });
+        started.map(|started| match started {
+            Ok(handle) => handle.join().unwrap_or_else(|err| panic::resume_unwind(err)),
+            Err(node) => f(node),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Edge, Hashlife};
+
+    fn assert_send<T: Send>() {}
+
+    fn acorn() -> Hashlife {
+        let cells = vec![
+            0,1,0,0,0,0,0,
+            0,0,0,1,0,0,0,
+            1,1,0,0,1,1,1,
+        ];
+        Hashlife::from_array(cells, 7, 3, Edge::Infinite)
+    }
+
+    /// A chaotic pattern in a node large enough to be forked.
+    fn chaos() -> (Hashlife, NodeId) {
+        let mut hashlife = acorn();
+        hashlife.advance(300);
+        let mut node = hashlife.top.unwrap();
+        while hashlife.store.get(node).level < PARALLEL_LEVEL + 2 {
+            node = hashlife.expand_empty_border(node);
+        }
+        (hashlife, node)
+    }
+
+    #[test]
+    fn hashlife_is_send() {
+        assert_send::<Hashlife>();
+    }
+
+    #[test]
+    fn fork_matches_serial_jump() {
+        let (mut hashlife, node) = chaos();
+        let forked = forked(&mut hashlife.store, &mut hashlife.cache, &hashlife.transitions, 8, |shared| fork_jump(shared, node));
+        hashlife.cache.jump.clear();
+        let serial = step::jump(&mut Serial::new(&mut hashlife.store, &mut hashlife.cache, &hashlife.transitions), node);
+        assert_eq!(forked, serial);
+    }
+
+    #[test]
+    fn fork_matches_serial_step() {
+        let (mut hashlife, node) = chaos();
+        hashlife.use_step_exponent(3);
+        let forked = forked(&mut hashlife.store, &mut hashlife.cache, &hashlife.transitions, 8, |shared| fork_step(shared, node));
+        hashlife.cache.step.clear();
+        hashlife.cache.jump.clear();
+        let serial = step::step(&mut Serial::new(&mut hashlife.store, &mut hashlife.cache, &hashlife.transitions), node);
+        assert_eq!(forked, serial);
+    }
+
+    #[test]
+    fn adopted_nodes_stay_canonical() {
+        let (mut hashlife, node) = chaos();
+        let capacity = hashlife.store.capacity();
+        let forked = forked(&mut hashlife.store, &mut hashlife.cache, &hashlife.transitions, 4, |shared| fork_jump(shared, node));
+        assert!(hashlife.store.capacity() > capacity);
+        // Every node built by the workers is found again, the unused slots
+        // between the shards are reused.
+        let len = hashlife.store.len();
+        hashlife.cache.jump.clear();
+        let serial = step::jump(&mut Serial::new(&mut hashlife.store, &mut hashlife.cache, &hashlife.transitions), node);
+        assert_eq!((forked, hashlife.store.len()), (serial, len));
+        let capacity = hashlife.store.capacity();
+        // The two single cells hold a slot each without being in the table.
+        for bits in 0..(capacity - len - 2) as u64 {
+            hashlife.store.leaf(3, bits << 32 | 0xdead_beef);
+        }
+        assert_eq!(hashlife.store.capacity(), capacity);
+    }
+}
@@ -0,0 +1,697 @@
+use std::collections::HashMap;
+
+use crate::rle_loader::RleData;
+use crate::sparse::Runs;
+use crate::store::{square, Nodes, Store, LEAF_LEVEL, MAX_LEVEL};
+use crate::{top_corner, Automata, BoundingBox, Content, Edge, Hashlife, HashlifeError, Node, NodeId, Rule, Transform};
+
+/// A rectangle of cells kept in a quadtree of its own, ready to be pasted
+/// into a universe with `Hashlife::paste`.
+pub struct Pattern {
+    universe: Hashlife,
+    bounds: BoundingBox,
+    metadata: Metadata,
+}
+
+/// The descriptive parts of a pattern file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub comments: Vec<String>,
+    /// The rule the pattern is meant to evolve by.
+    pub rule: Option<Rule>,
+    /// Where the file puts the top left cell, as a column to the right and a
+    /// row down from the origin.
+    pub position: Option<(isize, isize)>,
+}
+
+impl Pattern {
+    /// Create a pattern from an array of states, row by row from the top
+    /// left.
+    pub fn from_array(buffer: Vec<u8>, width: usize, height: usize) -> Self {
+        Self::try_from_array(buffer, width, height).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// A pattern of `width` by `height` cells with its top left cell at
+    /// `left`, `top`, holding the living cells at the given columns and rows
+    /// from there. The bounds grow to hold cells past the given size.
+    pub(crate) fn from_cells<I>(width: usize, height: usize, left: isize, top: isize, cells: I) -> Self
+    where
+        I: IntoIterator<Item = (usize, usize)>,
+    {
+        let mut runs = Runs::default();
+        let (mut width, mut height) = (width, height);
+        for (col, row) in cells {
+            runs.insert(col, row);
+            width = width.max(col + 1);
+            height = height.max(row + 1);
+        }
+        Self::from_runs(width, height, left, top, runs)
+    }
+
+    /// Like `from_cells`, with the cells already gathered in runs and the
+    /// size holding all of them.
+    pub(crate) fn from_runs(width: usize, height: usize, left: isize, top: isize, runs: Runs) -> Self {
+        let mut universe = Hashlife::new();
+        let node = universe.construct_from_runs(runs);
+        universe.top = Some(node);
+        universe.translate(left, top + 1);
+        let bounds = BoundingBox::from(top, top - height as isize + 1, left, left + width as isize - 1);
+        Self { universe, bounds, metadata: Metadata::default() }
+    }
+
+    /// Like `from_array`, but fails when the buffer does not hold `width *
+    /// height` valid states.
+    pub fn try_from_array(buffer: Vec<u8>, width: usize, height: usize) -> Result<Self, HashlifeError> {
+        let universe = Hashlife::try_from_array(buffer, width, height, Edge::Infinite)?;
+        let (left, top) = centred(width, height);
+        let bounds = BoundingBox::from(top, top - height as isize + 1, left, left + width as isize - 1);
+        Ok(Self { universe, bounds, metadata: Metadata::default() })
+    }
+
+    pub fn width(&self) -> usize {
+        self.bounds.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.bounds.height()
+    }
+
+    /// The rectangle holding the cells of the pattern.
+    pub fn bounds(&self) -> &BoundingBox {
+        &self.bounds
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+
+    /// The state of the cell at `x`, `y`. Cells outside of the bounds are
+    /// dead.
+    pub fn get(&self, x: isize, y: isize) -> Automata {
+        if !self.bounds.contains(x, y) {
+            return Automata::Dead;
+        }
+        self.universe.get(x, y).unwrap_or(Automata::Dead)
+    }
+
+    /// Apply a symmetry of the square to the pattern, about the origin of its
+    /// coordinates.
+    pub fn transform(&mut self, transform: Transform) {
+        self.universe.transform(transform);
+        let (x0, y0) = transform.apply(self.bounds.left, self.bounds.top);
+        let (x1, y1) = transform.apply(self.bounds.right, self.bounds.bottom);
+        self.bounds = BoundingBox::from(y0.max(y1), y0.min(y1), x0.min(x1), x0.max(x1));
+    }
+
+    /// Move the pattern by `dx` columns to the right and `dy` rows up.
+    pub fn translate(&mut self, dx: isize, dy: isize) {
+        self.universe.translate(dx, dy);
+        let b = self.bounds;
+        self.bounds = BoundingBox::from(b.top + dy, b.bottom + dy, b.left + dx, b.right + dx);
+    }
+}
+
+impl From<&RleData> for Pattern {
+    fn from(rle: &RleData) -> Self {
+        let (width, height) = (rle.width as usize, rle.height as usize);
+        if width == 0 || height == 0 {
+            // No universe holds zero cells, an empty file is read like
+            // `rle::read` reads it.
+            let (left, top) = centred(width, height);
+            return Self::from_cells(width, height, left, top, std::iter::empty());
+        }
+        let cells = rle.cells.iter().map(|&cell| cell as u8).collect();
+        Self::from_array(cells, width, height)
+    }
+}
+
+/// The top left cell of a rectangle placed on the origin like
+/// `Hashlife::from_array` places its cells.
+pub(crate) fn centred(width: usize, height: usize) -> (isize, isize) {
+    (-(width as isize / 2), height as isize - height as isize / 2 - 1)
+}
+
+/// How the cells of a pattern are combined with the cells of the universe it
+/// is pasted into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CombineMode {
+    /// The rectangle of the pattern replaces the cells below it.
+    Copy,
+    /// Cells alive in the universe or in the pattern are alive.
+    Or,
+    /// Cells inside the rectangle of the pattern stay alive only if they are
+    /// alive in the pattern as well.
+    And,
+    /// Cells alive in the pattern are flipped.
+    Xor,
+    /// Cells alive in the pattern are killed.
+    Clear,
+}
+
+/// Cell by cell operations on nodes of the same level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Op {
+    Or,
+    And,
+    Xor,
+    AndNot,
+}
+
+impl Op {
+    fn apply(self, a: u64, b: u64) -> u64 {
+        match self {
+            Op::Or => a | b,
+            Op::And => a & b,
+            Op::Xor => a ^ b,
+            Op::AndNot => a & !b,
+        }
+    }
+}
+
+/// Results of the node operations of a single edit of a universe.
+#[derive(Default)]
+pub(crate) struct Memo {
+    imported: HashMap<NodeId, NodeId>,
+    shifted: HashMap<(NodeId, usize, usize), NodeId>,
+    combined: HashMap<(Op, NodeId, NodeId), NodeId>,
+}
+
+impl Hashlife {
+    /// Paste a pattern with its top left cell on `x`, `y`. The trees are
+    /// combined node by node, so empty and identical subtrees are not visited.
+    /// An infinite universe grows to fit the pattern, a torus wraps it around
+    /// its edges and a truncated universe drops the cells outside of it.
+    pub fn paste(&mut self, pattern: &Pattern, x: isize, y: isize, mode: CombineMode) {
+        self.try_paste(pattern, x, y, mode).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `paste`, but fails when the pattern would reach more than 2^61
+    /// cells from the origin.
+    pub fn try_paste(&mut self, pattern: &Pattern, x: isize, y: isize, mode: CombineMode) -> Result<(), HashlifeError> {
+        let (width, height) = (pattern.width() as isize, pattern.height() as isize);
+        let bottom = y.checked_sub(height - 1).ok_or(HashlifeError::TooLarge)?;
+        let right = x.checked_add(width - 1).ok_or(HashlifeError::TooLarge)?;
+        let area = BoundingBox::from(y, bottom, x, right);
+        if !BoundingBox::square(MAX_LEVEL).encloses(&area) {
+            return Err(HashlifeError::TooLarge);
+        }
+        let mut top = match self.top {
+            Some(top) => top,
+            None => self.empty(0),
+        };
+        if self.edge == Edge::Infinite {
+            while !BoundingBox::square(self.store.get(top).level).encloses(&area) {
+                top = self.expand_empty_border(top);
+            }
+        }
+        let level = self.store.get(top).level;
+        let side = 1isize << level;
+        let (left, top_y) = top_corner(level);
+
+        let mut memo = Memo::default();
+        let source = pattern.universe.top.expect("a pattern always has a tree");
+        let node = self.import(&pattern.universe.store, source, &mut memo);
+        let (source_left, source_top) = top_corner(pattern.universe.store.get(source).level);
+        let (node_x, node_y) = (source_left + x - pattern.bounds.left, source_top + y - pattern.bounds.top);
+
+        // The copies of the pattern overlapping the universe, a torus repeats
+        // the pattern every `side` cells.
+        let offsets = match self.edge {
+            Edge::Torus => {
+                let dx = (x - left).rem_euclid(side) + left - x;
+                let dy = top_y - (top_y - y).rem_euclid(side) - y;
+                let columns = ((x + dx - left) + width + side - 1) / side;
+                let rows = ((top_y - y - dy) + height + side - 1) / side;
+                (0..columns)
+                    .flat_map(|column| (0..rows).map(move |row| (dx - column * side, dy + row * side)))
+                    .collect()
+            },
+            _ => vec![(0, 0)],
+        };
+        let mut cells = self.empty(level);
+        let mut mask = self.empty(level);
+        for (dx, dy) in offsets {
+            let placed = self.place(node, node_x + dx, node_y + dy, level, &mut memo);
+            cells = self.combine(Op::Or, cells, placed, &mut memo);
+            let rectangle = BoundingBox::from(area.top + dy, area.bottom + dy, area.left + dx, area.right + dx);
+            let rectangle = self.rectangle(level, left, top_y, &rectangle);
+            mask = self.combine(Op::Or, mask, rectangle, &mut memo);
+        }
+
+        let result = match mode {
+            CombineMode::Copy => {
+                let outside = self.combine(Op::AndNot, top, mask, &mut memo);
+                self.combine(Op::Or, outside, cells, &mut memo)
+            },
+            CombineMode::Or => self.combine(Op::Or, top, cells, &mut memo),
+            CombineMode::And => {
+                let outside = self.combine(Op::AndNot, top, mask, &mut memo);
+                let inside = self.combine(Op::And, top, cells, &mut memo);
+                self.combine(Op::Or, outside, inside, &mut memo)
+            },
+            CombineMode::Xor => self.combine(Op::Xor, top, cells, &mut memo),
+            CombineMode::Clear => self.combine(Op::AndNot, top, cells, &mut memo),
+        };
+        self.top = Some(result);
+        Ok(())
+    }
+
+    /// The cells inside a rectangle as a pattern of their own. Nodes lying
+    /// entirely inside the rectangle are copied whole, only the nodes
+    /// crossing its border are rebuilt.
+    pub fn extract(&mut self, area: &BoundingBox) -> Pattern {
+        self.try_extract(area).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `extract`, but fails when the rectangle is inside out or reaches
+    /// more than 2^61 cells from the origin.
+    pub fn try_extract(&mut self, area: &BoundingBox) -> Result<Pattern, HashlifeError> {
+        if area.top < area.bottom || area.right < area.left {
+            return Err(HashlifeError::InvalidRectangle);
+        }
+        if !BoundingBox::square(MAX_LEVEL).encloses(area) {
+            return Err(HashlifeError::TooLarge);
+        }
+        let mut universe = Hashlife::new();
+        universe.set_rule(self.rule.clone());
+        let mut top = match self.top {
+            Some(top) => {
+                let masked = self.masked(top, area, &mut Memo::default());
+                universe.import(&self.store, masked, &mut Memo::default())
+            },
+            None => universe.empty(0),
+        };
+        while !BoundingBox::square(universe.store.get(top).level).encloses(area) {
+            top = universe.expand_empty_border(top);
+        }
+        universe.top = Some(top);
+        Ok(Pattern { universe, bounds: *area, metadata: Metadata::default() })
+    }
+
+    /// Kill every cell outside of a rectangle.
+    pub fn crop(&mut self, area: &BoundingBox) {
+        self.try_crop(area).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `crop`, but fails when the rectangle is inside out.
+    pub fn try_crop(&mut self, area: &BoundingBox) -> Result<(), HashlifeError> {
+        if area.top < area.bottom || area.right < area.left {
+            return Err(HashlifeError::InvalidRectangle);
+        }
+        if let Some(top) = self.top {
+            self.top = Some(self.masked(top, area, &mut Memo::default()));
+        }
+        Ok(())
+    }
+
+    /// A top node with only the cells inside a rectangle left alive.
+    fn masked(&mut self, top: NodeId, area: &BoundingBox, memo: &mut Memo) -> NodeId {
+        let level = self.store.get(top).level;
+        let (left, top_y) = top_corner(level);
+        let rectangle = self.rectangle(level, left, top_y, area);
+        self.combine(Op::And, top, rectangle, memo)
+    }
+
+    /// Copy a node of another universe into this one.
+    fn import(&mut self, store: &Store, node: NodeId, memo: &mut Memo) -> NodeId {
+        if let Some(&id) = memo.imported.get(&node) {
+            return id;
+        }
+        let n = store.get(node);
+        let id = match n.content {
+            Content::Leaf(bits) => self.store.leaf(n.level, bits),
+            Content::Branch(c) => {
+                let nw = self.import(store, c.nw, memo);
+                let ne = self.import(store, c.ne, memo);
+                let sw = self.import(store, c.sw, memo);
+                let se = self.import(store, c.se, memo);
+                self.join(nw, ne, sw, se)
+            },
+        };
+        memo.imported.insert(node, id);
+        id
+    }
+
+    /// The cells of `node` moved so that its top left cell is at `x`, `y`, as
+    /// a top node of the given level.
+    pub(crate) fn place(&mut self, node: NodeId, x: isize, y: isize, level: usize, memo: &mut Memo) -> NodeId {
+        let inner = self.store.get(node).level;
+        let (side, inner_side) = (1isize << level, 1isize << inner);
+        let (left, top) = top_corner(level);
+        if x >= left + side || x + inner_side <= left || y <= top - side || y - inner_side >= top {
+            return self.empty(level);
+        }
+        // Surround the node with enough empty space to cut the top node out
+        // of it at any overlapping offset.
+        let canvas_level = level.max(inner) + 2;
+        let mut padded = node;
+        for padding in inner..canvas_level - 2 {
+            let e = self.empty(padding);
+            padded = self.join(padded, e, e, e);
+        }
+        let e = self.empty(canvas_level - 2);
+        let nw = self.join(e, e, e, padded);
+        let e = self.empty(canvas_level - 1);
+        let canvas = self.join(nw, e, e, e);
+        let margin = 1isize << (canvas_level - 2);
+        self.window(canvas, (left - x + margin) as usize, (y + margin - top) as usize, level, memo)
+    }
+
+    /// The node of the given level whose top left cell is `ox` columns right
+    /// of and `oy` rows below the top left cell of `node`. The window must lie
+    /// inside the node.
+    pub(crate) fn window(&mut self, node: NodeId, ox: usize, oy: usize, level: usize, memo: &mut Memo) -> NodeId {
+        let n = *self.store.get(node);
+        if level == n.level {
+            return node;
+        }
+        let size = 1 << level;
+        let half = 1 << (n.level - 1);
+        if ox % half + size <= half && oy % half + size <= half {
+            let c = self.store.split(node);
+            let quadrant = [c.nw, c.ne, c.sw, c.se][oy / half * 2 + ox / half];
+            return self.window(quadrant, ox % half, oy % half, level, memo);
+        }
+        if level + 1 == n.level {
+            return self.shift(node, ox, oy, memo);
+        }
+        // The window straddles the quadrants, shift the node twice its size
+        // that is aligned to its side and holds it.
+        let (ax, ay) = (ox / size * size, oy / size * size);
+        let nw = self.window(node, ax, ay, level, memo);
+        let ne = self.window(node, ax + size, ay, level, memo);
+        let sw = self.window(node, ax, ay + size, level, memo);
+        let se = self.window(node, ax + size, ay + size, level, memo);
+        let aligned = self.join(nw, ne, sw, se);
+        self.shift(aligned, ox - ax, oy - ay, memo)
+    }
+
+    /// The node one level lower whose top left cell is `ox` columns right of
+    /// and `oy` rows below the top left cell of `node`, both offsets are at
+    /// most half of its side. Unless the offsets are aligned the quadrants of
+    /// the result are the shifted nodes made of four grandchildren.
+    fn shift(&mut self, node: NodeId, ox: usize, oy: usize, memo: &mut Memo) -> NodeId {
+        let n = *self.store.get(node);
+        let half = 1 << (n.level - 1);
+        if ox % half == 0 && oy % half == 0 {
+            let c = self.store.split(node);
+            return [c.nw, c.ne, c.sw, c.se][oy / half * 2 + ox / half];
+        }
+        if n.level <= LEAF_LEVEL + 1 {
+            let rows = self.store.rows(node);
+            let bits = (0..half).fold(0u64, |bits, row| {
+                bits | (u64::from(rows[oy + row] >> ox) & ((1 << half) - 1)) << (row * 8)
+            });
+            return self.store.leaf(n.level - 1, bits);
+        }
+        if let Some(&id) = memo.shifted.get(&(node, ox, oy)) {
+            return id;
+        }
+
+        let quarter = half / 2;
+        let c = self.store.split(node);
+        let quadrants = [
+            self.store.split(c.nw),
+            self.store.split(c.ne),
+            self.store.split(c.sw),
+            self.store.split(c.se),
+        ];
+        let grid = |row: usize, col: usize| {
+            let q = quadrants[row / 2 * 2 + col / 2];
+            [q.nw, q.ne, q.sw, q.se][row % 2 * 2 + col % 2]
+        };
+        let mut children = [NodeId::DEAD; 4];
+        for (i, child) in children.iter_mut().enumerate() {
+            let (x, y) = (ox + i % 2 * quarter, oy + i / 2 * quarter);
+            let (col, row) = ((x / quarter).min(2), (y / quarter).min(2));
+            let block = self.join(grid(row, col), grid(row, col + 1), grid(row + 1, col), grid(row + 1, col + 1));
+            *child = self.shift(block, x - col * quarter, y - row * quarter, memo);
+        }
+        let [nw, ne, sw, se] = children;
+        let result = self.join(nw, ne, sw, se);
+        memo.shifted.insert((node, ox, oy), result);
+        result
+    }
+
+    /// The node of the given level with its top left cell at `left`, `top` in
+    /// which exactly the cells inside `area` are alive.
+    fn rectangle(&mut self, level: usize, left: isize, top: isize, area: &BoundingBox) -> NodeId {
+        let side = 1isize << level;
+        let bounds = BoundingBox::from(top, top - side + 1, left, left + side - 1);
+        if !bounds.collides(area) {
+            return self.empty(level);
+        }
+        if area.encloses(&bounds) {
+            return self.full(level);
+        }
+        if level <= LEAF_LEVEL {
+            let mut bits = 0;
+            for row in 0..side {
+                for col in 0..side {
+                    if area.contains(left + col, top - row) {
+                        bits |= 1 << (row * 8 + col);
+                    }
+                }
+            }
+            return self.store.leaf(level, bits);
+        }
+        let half = side / 2;
+        let nw = self.rectangle(level - 1, left, top, area);
+        let ne = self.rectangle(level - 1, left + half, top, area);
+        let sw = self.rectangle(level - 1, left, top - half, area);
+        let se = self.rectangle(level - 1, left + half, top - half, area);
+        self.join(nw, ne, sw, se)
+    }
+
+    /// Construct a Quad Node of living cells at the specified level.
+    fn full(&mut self, level: usize) -> NodeId {
+        if level <= LEAF_LEVEL {
+            return self.store.leaf(level, square(1 << level));
+        }
+        let child = self.full(level - 1);
+        self.join(child, child, child, child)
+    }
+
+    /// Combine two nodes of the same level cell by cell.
+    fn combine(&mut self, op: Op, a: NodeId, b: NodeId, memo: &mut Memo) -> NodeId {
+        let (na, nb) = (*self.store.get(a), *self.store.get(b));
+        // Empty and identical nodes need no work, they make up most of a
+        // universe.
+        match op {
+            Op::Or if na.population == 0 => return b,
+            Op::Or if nb.population == 0 || a == b => return a,
+            Op::And if na.population == 0 || a == b => return a,
+            Op::And if nb.population == 0 || is_full(&na) => return b,
+            Op::And if is_full(&nb) => return a,
+            Op::Xor if na.population == 0 => return b,
+            Op::Xor if nb.population == 0 => return a,
+            Op::AndNot if na.population == 0 || nb.population == 0 => return a,
+            Op::Xor | Op::AndNot if a == b => return self.empty(na.level),
+            Op::AndNot if is_full(&nb) => return self.empty(na.level),
+            _ => (),
+        }
+        if let Some(&id) = memo.combined.get(&(op, a, b)) {
+            return id;
+        }
+        let result = match (na.content, nb.content) {
+            (Content::Leaf(x), Content::Leaf(y)) => self.store.leaf(na.level, op.apply(x, y)),
+            _ => {
+                let ca = self.store.split(a);
+                let cb = self.store.split(b);
+                let nw = self.combine(op, ca.nw, cb.nw, memo);
+                let ne = self.combine(op, ca.ne, cb.ne, memo);
+                let sw = self.combine(op, ca.sw, cb.sw, memo);
+                let se = self.combine(op, ca.se, cb.se, memo);
+                self.join(nw, ne, sw, se)
+            },
+        };
+        memo.combined.insert((op, a, b), result);
+        result
+    }
+}
+
+/// Whether every cell of a node is alive.
+fn is_full(node: &Node) -> bool {
+    1u128.checked_shl(2 * node.level as u32) == Some(node.population)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rle_loader;
+
+    /// Random cells, the same for the same seed.
+    fn noise(width: usize, height: usize, seed: u32) -> Vec<u8> {
+        crate::random(seed).take(width * height).map(|n| (n & 1) as u8).collect()
+    }
+
+    fn expected(mode: CombineMode, universe: Automata, pattern: Automata, inside: bool) -> Automata {
+        let (universe, pattern) = (universe.is_alive(), pattern.is_alive());
+        let alive = match mode {
+            CombineMode::Copy => if inside { pattern } else { universe },
+            CombineMode::Or => universe || pattern,
+            CombineMode::And => universe && (pattern || !inside),
+            CombineMode::Xor => universe != pattern,
+            CombineMode::Clear => universe && !pattern,
+        };
+        Automata::from(alive as usize)
+    }
+
+    #[test]
+    fn paste_matches_cell_by_cell() {
+        let pattern = Pattern::from_array(noise(13, 11, 2), 13, 11);
+        let modes = [CombineMode::Copy, CombineMode::Or, CombineMode::And, CombineMode::Xor, CombineMode::Clear];
+        // The last universe is smaller than the pattern.
+        let cases = [(Edge::Infinite, 32, -7, 5), (Edge::Truncate, 32, 9, 14), (Edge::Truncate, 32, -21, -10), (Edge::Truncate, 8, -9, 6)];
+        for &(edge, size, x, y) in &cases {
+            for &mode in &modes {
+                let original = Hashlife::from_array(noise(size, size, 1), size, size, edge);
+                let mut hashlife = Hashlife::from_array(noise(size, size, 1), size, size, edge);
+                hashlife.paste(&pattern, x, y, mode);
+                assert_eq!(hashlife.max_level(), original.max_level());
+                let half = size as isize / 2;
+                for cx in -half..half {
+                    for cy in -half..half {
+                        let (px, py) = (cx - x + pattern.bounds().left(), cy - y + pattern.bounds().top());
+                        let inside = pattern.bounds().contains(px, py);
+                        let cell = expected(mode, original.get(cx, cy).unwrap(), pattern.get(px, py), inside);
+                        assert_eq!(hashlife.get(cx, cy), Some(cell), "{:?} at ({}, {})", mode, cx, cy);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_rle_converts_to_an_empty_pattern() {
+        for source in &["x = 0, y = 0\n!", "x = 0, y = 3\n!"] {
+            let pattern = Pattern::from(&rle_loader::load(source));
+            let read = crate::rle::read(source).unwrap();
+            assert_eq!(pattern.bounds(), read.bounds(), "{}", source);
+            assert_eq!(pattern.width(), 0);
+            let mut hashlife = Hashlife::from_array(vec![1; 4], 2, 2, Edge::Infinite);
+            hashlife.paste(&pattern, -1, 0, CombineMode::Copy);
+            assert_eq!(hashlife.get(-1, 0), Some(Automata::Alive));
+        }
+    }
+
+    #[test]
+    fn paste_grows_infinite_universe() {
+        let glider = rle_loader::load("x = 3, y = 3\nbo$2bo$3o!");
+        let mut hashlife = Hashlife::from_array(vec![0; 4], 2, 2, Edge::Infinite);
+        hashlife.paste(&Pattern::from(&glider), 1000, -3000, CombineMode::Or);
+        assert_eq!(hashlife.max_level(), 13);
+        assert_eq!(hashlife.store.get(hashlife.top.unwrap()).population, 5);
+        assert_eq!(hashlife.get(1001, -3000), Some(Automata::Alive));
+        assert_eq!(hashlife.get(1002, -3001), Some(Automata::Alive));
+        assert_eq!(hashlife.get(1000, -3002), Some(Automata::Alive));
+
+        // A glider moves by one cell diagonally every four generations.
+        hashlife.advance(4);
+        assert_eq!(hashlife.get(1002, -3001), Some(Automata::Alive));
+        assert_eq!(hashlife.get(1003, -3002), Some(Automata::Alive));
+        assert_eq!(hashlife.get(1001, -3003), Some(Automata::Alive));
+        assert_eq!(hashlife.store.get(hashlife.top.unwrap()).population, 5);
+    }
+
+    #[test]
+    fn paste_wraps_around_torus() {
+        let block = Pattern::from_array(vec![1; 9], 3, 3);
+        let mut hashlife = Hashlife::from_array(vec![0; 64], 8, 8, Edge::Torus);
+        hashlife.paste(&block, 3, -3, CombineMode::Copy);
+        assert_eq!(hashlife.max_level(), 3);
+        assert_eq!(hashlife.store.get(hashlife.top.unwrap()).population, 9);
+        for (x, y) in [(3, -3), (-4, -3), (-3, -4), (3, 3), (-4, 3), (-3, 3)] {
+            assert_eq!(hashlife.get(x, y), Some(Automata::Alive), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn extract_keeps_cells_and_offset() {
+        let mut hashlife = Hashlife::from_array(noise(32, 32, 1), 32, 32, Edge::Infinite);
+        let area = BoundingBox::from(9, -3, -12, 20);
+        let pattern = hashlife.extract(&area);
+        assert_eq!(pattern.bounds(), &area);
+        assert_eq!((pattern.width(), pattern.height()), (33, 13));
+        for x in -16..16 {
+            for y in -16..16 {
+                let expected = if area.contains(x, y) { hashlife.get(x, y).unwrap() } else { Automata::Dead };
+                assert_eq!(pattern.get(x, y), expected, "({}, {})", x, y);
+            }
+        }
+
+        // Pasted back at its offset over an empty universe it is a crop.
+        let mut copy = Hashlife::from_array(vec![0; 4], 2, 2, Edge::Infinite);
+        copy.paste(&pattern, area.left(), area.top(), CombineMode::Copy);
+        hashlife.crop(&area);
+        for x in -16..16 {
+            for y in -16..16 {
+                assert_eq!(copy.get(x, y), hashlife.get(x, y), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn extract_huge_rectangle() {
+        let mut hashlife = Hashlife::from_array(noise(8, 8, 2), 8, 8, Edge::Infinite);
+        let area = BoundingBox::from((1 << 61) - 1, -(1 << 61), -(1 << 61), (1 << 61) - 1);
+        let pattern = hashlife.extract(&area);
+        assert_eq!(pattern.width(), 1 << 62);
+        assert_eq!(pattern.get(-4, 3), hashlife.get(-4, 3).unwrap());
+    }
+
+    #[test]
+    #[should_panic]
+    fn extract_past_the_coordinates_panics() {
+        let mut hashlife = Hashlife::from_array(vec![0; 4], 2, 2, Edge::Infinite);
+        hashlife.extract(&BoundingBox::from(isize::MAX / 2, isize::MIN / 2, isize::MIN / 2, isize::MAX / 2));
+    }
+
+    #[test]
+    fn try_edits_reject_bad_rectangles() {
+        let mut hashlife = Hashlife::from_array(noise(8, 8, 3), 8, 8, Edge::Infinite);
+        let population = hashlife.population();
+        let inside_out = BoundingBox::from(0, 1, 0, 0);
+        assert_eq!(hashlife.try_extract(&inside_out).err(), Some(HashlifeError::InvalidRectangle));
+        assert_eq!(hashlife.try_crop(&inside_out), Err(HashlifeError::InvalidRectangle));
+        let far = BoundingBox::from(isize::MAX, 0, 0, 0);
+        assert_eq!(hashlife.try_extract(&far).err(), Some(HashlifeError::TooLarge));
+
+        let pattern = hashlife.extract(&BoundingBox::from(3, -4, -4, 3));
+        assert_eq!(hashlife.try_paste(&pattern, isize::MAX - 2, 0, CombineMode::Or), Err(HashlifeError::TooLarge));
+        assert_eq!(hashlife.try_paste(&pattern, 0, isize::MIN + 2, CombineMode::Or), Err(HashlifeError::TooLarge));
+        assert_eq!(hashlife.try_paste(&pattern, 1 << 61, 0, CombineMode::Or), Err(HashlifeError::TooLarge));
+        assert_eq!(hashlife.population(), population);
+        assert_eq!(hashlife.try_paste(&pattern, (1 << 61) - 8, 0, CombineMode::Or), Ok(()));
+        assert_eq!(hashlife.population(), 2 * population);
+    }
+
+    #[test]
+    fn masking_keeps_aligned_nodes() {
+        let mut hashlife = Hashlife::from_array(noise(64, 64, 3), 64, 64, Edge::Infinite);
+        let top = hashlife.top.unwrap();
+        // The north east quadrant of the tree is kept as it is.
+        let masked = hashlife.masked(top, &BoundingBox::from(31, 0, 0, 31), &mut Memo::default());
+        let (c, mc) = (hashlife.store.children(top), hashlife.store.children(masked));
+        assert_eq!(mc.ne, c.ne);
+        assert_eq!(hashlife.store.get(mc.nw).population, 0);
+        assert_eq!(hashlife.store.get(masked).population, hashlife.store.get(c.ne).population);
+    }
+
+    #[test]
+    fn crop_kills_cells_outside() {
+        let mut hashlife = Hashlife::from_array(vec![1; 64], 8, 8, Edge::Truncate);
+        hashlife.crop(&BoundingBox::from(1, -2, -3, 20));
+        assert_eq!(hashlife.store.get(hashlife.top.unwrap()).population, 4 * 7);
+        assert_eq!(hashlife.get(-3, 1), Some(Automata::Alive));
+        assert_eq!(hashlife.get(-4, 1), Some(Automata::Dead));
+        assert_eq!(hashlife.get(3, 2), Some(Automata::Dead));
+        assert_eq!(hashlife.get(3, -2), Some(Automata::Alive));
+    }
+}
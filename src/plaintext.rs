@@ -0,0 +1,131 @@
+//! The plaintext format of `.cells` files. Lines starting with `!` are
+//! comments, `!Name:` and `!Author:` ones included. Every other line is a
+//! row of cells from the top, `.` for a dead cell and `O` for a living one.
+
+use std::io::{self, Write};
+
+use crate::format::{rows_down, written_area};
+use crate::pattern::centred;
+use crate::rle::WriteOptions;
+use crate::{BoundingBox, Hashlife, HashlifeError, Metadata, Pattern};
+
+/// Read a pattern from a plaintext file, centred on the origin like
+/// `Pattern::from_array` places its cells. Rows may be shorter than the
+/// widest one and `*` is read as a living cell too.
+pub fn read(source: &str) -> Result<Pattern, HashlifeError> {
+    let mut metadata = Metadata::default();
+    let mut rows = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim_end();
+        match line.strip_prefix('!') {
+            Some(comment) => {
+                let comment = comment.trim();
+                if let Some(name) = comment.strip_prefix("Name:") {
+                    metadata.name = Some(name.trim().to_string());
+                } else if let Some(author) = comment.strip_prefix("Author:") {
+                    metadata.author = Some(author.trim().to_string());
+                } else {
+                    metadata.comments.push(comment.to_string());
+                }
+            },
+            None => rows.push((index + 1, line)),
+        }
+    }
+    // Blank lines only count as rows between rows of cells.
+    while rows.last().is_some_and(|(_, row)| row.is_empty()) {
+        rows.pop();
+    }
+    let first = rows.iter().position(|(_, row)| !row.is_empty()).unwrap_or(rows.len());
+    let rows = &rows[first..];
+
+    let mut cells = Vec::new();
+    for (r, &(number, line)) in rows.iter().enumerate() {
+        for (col, c) in line.chars().enumerate() {
+            match c {
+                'O' | '*' => cells.push((col, r)),
+                '.' => (),
+                c => return Err(HashlifeError::parse(number, col + 1, &format!("unexpected character `{}`", c))),
+            }
+        }
+    }
+    let width = rows.iter().map(|(_, row)| row.chars().count()).max().unwrap_or(0);
+    let (left, top) = centred(width, rows.len());
+    let mut pattern = Pattern::from_cells(width, rows.len(), left, top, cells);
+    *pattern.metadata_mut() = metadata;
+    Ok(pattern)
+}
+
+/// Write the cells of a universe inside a rectangle as a plaintext file.
+/// Without a rectangle the smallest one holding every living cell is
+/// written. The format has no position, `options.position` is ignored.
+pub fn write<W: Write>(hashlife: &Hashlife, area: Option<&BoundingBox>, writer: &mut W, options: &WriteOptions) -> io::Result<()> {
+    let area = written_area(hashlife, area)?;
+    if let Some(name) = &options.name {
+        writeln!(writer, "!Name: {}", name)?;
+    }
+    if let Some(author) = &options.author {
+        writeln!(writer, "!Author: {}", author)?;
+    }
+    for comment in &options.comments {
+        writeln!(writer, "!{}", comment)?;
+    }
+    let area = match area {
+        Some(area) => area,
+        None => return Ok(()),
+    };
+    let mut cells = rows_down(hashlife, &area).into_iter().peekable();
+    for y in -area.top()..=-area.bottom() {
+        let mut row = vec![b'.'; area.width()];
+        while let Some((_, x)) = cells.next_if(|&(row, _)| row == y) {
+            row[(x - area.left()) as usize] = b'O';
+        }
+        writer.write_all(&row)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Automata, Edge};
+
+    #[test]
+    fn read_rows_and_comments() {
+        let pattern = read("!Name: Beehive with tail\r\n!Author: Someone\r\n!A still life.\r\n!\r\n.OO\r\nO..O\r\n.OO\r\n\r\n...\r\nO.\r\n\r\n").unwrap();
+        let metadata = pattern.metadata();
+        assert_eq!(metadata.name.as_deref(), Some("Beehive with tail"));
+        assert_eq!(metadata.author.as_deref(), Some("Someone"));
+        assert_eq!(metadata.comments, vec!["A still life.", ""]);
+        assert_eq!((pattern.width(), pattern.height()), (4, 6));
+        assert_eq!(*pattern.bounds(), BoundingBox::from(2, -3, -2, 1));
+        assert_eq!(pattern.get(-2, -3), Automata::Alive);
+        assert_eq!(pattern.get(-1, 2), Automata::Alive);
+        assert_eq!(pattern.get(-2, 2), Automata::Dead);
+
+        assert_eq!(read(".O\nOx").err(), Some(HashlifeError::parse(2, 2, "unexpected character `x`")));
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let cells = vec![
+            0,1,1,0,0,
+            0,0,0,0,0,
+            1,0,0,1,1,
+        ];
+        let hashlife = Hashlife::from_array(cells, 5, 3, Edge::Infinite);
+        let options = WriteOptions { name: Some("Sample".to_string()), comments: vec!["Two rows".to_string()], ..WriteOptions::default() };
+        let mut out = Vec::new();
+        write(&hashlife, None, &mut out, &options).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "!Name: Sample\n!Two rows\n.OO..\n.....\nO..OO\n");
+
+        let pattern = read(&text).unwrap();
+        assert_eq!(pattern.bounds(), &hashlife.live_bounds().unwrap());
+        for x in -3..3 {
+            for y in -2..2 {
+                assert_eq!(Some(pattern.get(x, y)), hashlife.get(x, y), "({}, {})", x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+
+use crate::store::Store;
+use crate::{top_corner, BoundingBox, Content, Hashlife, NodeId};
+
+/// A side of a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Side {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// What is known of the margin of a node on a side.
+#[derive(Clone, Copy, Debug)]
+enum Margin {
+    Exact(usize),
+    /// No cell is closer to the side than this.
+    AtLeast(usize),
+}
+
+impl Hashlife {
+    /// The smallest rectangle holding every living cell, or `None` when every
+    /// cell is dead. Each side is searched from the outside in, skipping the
+    /// nodes that cannot hold a cell closer to it than one already found, so
+    /// mostly the nodes along the edges of the pattern are visited.
+    pub fn live_bounds(&self) -> Option<BoundingBox> {
+        let top = self.top?;
+        let n = self.store.get(top);
+        if n.population == 0 {
+            return None;
+        }
+        let side = 1isize << n.level;
+        let (left, top_y) = top_corner(n.level);
+        let mut margins = HashMap::new();
+        let mut margin = |s| self.margin(top, s, usize::MAX, &mut margins).expect("the node has a population") as isize;
+        Some(BoundingBox::from(
+            top_y - margin(Side::Top),
+            top_y - side + 1 + margin(Side::Bottom),
+            left + margin(Side::Left),
+            left + side - 1 - margin(Side::Right),
+        ))
+    }
+
+    /// Number of living cells in the universe.
+    pub fn population(&self) -> u128 {
+        self.top.map_or(0, |top| self.store.get(top).population)
+    }
+
+    /// Number of living cells inside a rectangle. Nodes entirely inside it
+    /// count as a whole, only the nodes crossing its border are visited.
+    pub fn population_in(&self, area: &BoundingBox) -> u128 {
+        let top = match self.top {
+            Some(top) => top,
+            None => return 0,
+        };
+        let (left, top_y) = top_corner(self.store.get(top).level);
+        self.population_of(top, left, top_y, area)
+    }
+
+    fn population_of(&self, node: NodeId, left: isize, top: isize, area: &BoundingBox) -> u128 {
+        let n = self.store.get(node);
+        let side = 1isize << n.level;
+        let bounds = BoundingBox::from(top, top - side + 1, left, left + side - 1);
+        if n.population == 0 || !area.collides(&bounds) {
+            return 0;
+        }
+        if area.encloses(&bounds) {
+            return n.population;
+        }
+        match n.content {
+            Content::Leaf(bits) => (0..64)
+                .filter(|&bit| bits >> bit & 1 == 1 && area.contains(left + bit % 8, top - bit / 8))
+                .count() as u128,
+            Content::Branch(c) => {
+                let half = side / 2;
+                self.population_of(c.nw, left, top, area)
+                    + self.population_of(c.ne, left + half, top, area)
+                    + self.population_of(c.sw, left, top - half, area)
+                    + self.population_of(c.se, left + half, top - half, area)
+            },
+        }
+    }
+
+    /// The coordinates of every living cell, leaf by leaf. Empty subtrees
+    /// are skipped.
+    pub fn live_cells(&self) -> LiveCells<'_> {
+        LiveCells::new(self, None)
+    }
+
+    /// The coordinates of the living cells inside a rectangle, like
+    /// `live_cells`.
+    pub fn live_cells_in(&self, area: &BoundingBox) -> LiveCells<'_> {
+        LiveCells::new(self, Some(*area))
+    }
+
+    /// Number of empty columns or rows between a side of a node and its
+    /// closest living cell, if it is below `limit`. A child is searched with
+    /// the best margin found so far as its limit, and the children away from
+    /// the side only when the ones along it are empty.
+    fn margin(&self, node: NodeId, side: Side, limit: usize, margins: &mut HashMap<(NodeId, Side), Margin>) -> Option<usize> {
+        let n = self.store.get(node);
+        if n.population == 0 || limit == 0 {
+            return None;
+        }
+        match margins.get(&(node, side)) {
+            Some(&Margin::Exact(margin)) => return Some(margin).filter(|&margin| margin < limit),
+            Some(&Margin::AtLeast(bound)) if bound >= limit => return None,
+            _ => (),
+        }
+        let margin = match n.content {
+            Content::Leaf(bits) => {
+                let last = (1 << n.level) - 1;
+                // Every row folded onto the first one.
+                let columns = (0..8).fold(0, |columns, row| columns | (bits >> (row * 8) & 0xff));
+                let margin = match side {
+                    Side::Left => columns.trailing_zeros() as usize,
+                    Side::Right => last - (63 - columns.leading_zeros() as usize),
+                    Side::Top => bits.trailing_zeros() as usize / 8,
+                    Side::Bottom => last - (63 - bits.leading_zeros() as usize) / 8,
+                };
+                margins.insert((node, side), Margin::Exact(margin));
+                return Some(margin).filter(|&margin| margin < limit);
+            },
+            Content::Branch(c) => {
+                let half = 1 << (n.level - 1);
+                let (near, far) = match side {
+                    Side::Left => ([c.nw, c.sw], [c.ne, c.se]),
+                    Side::Right => ([c.ne, c.se], [c.nw, c.sw]),
+                    Side::Top => ([c.nw, c.ne], [c.sw, c.se]),
+                    Side::Bottom => ([c.sw, c.se], [c.nw, c.ne]),
+                };
+                let mut closest = |children: [NodeId; 2], limit: usize| {
+                    children.iter().fold(None, |best, &child| self.margin(child, side, best.unwrap_or(limit), margins).or(best))
+                };
+                // A cell in the near children is less than `half` away, so
+                // when none beats a limit above that they are empty.
+                match closest(near, limit) {
+                    Some(margin) => Some(margin),
+                    None if limit > half => closest(far, limit - half).map(|margin| margin + half),
+                    None => None,
+                }
+            },
+        };
+        let known = margin.map_or(Margin::AtLeast(limit), Margin::Exact);
+        margins.insert((node, side), known);
+        margin
+    }
+}
+
+/// Iterator over the coordinates of living cells, returned by
+/// `Hashlife::live_cells`.
+pub struct LiveCells<'a> {
+    store: &'a Store,
+    area: Option<BoundingBox>,
+    /// Nodes left to visit with their top left cell.
+    stack: Vec<(NodeId, isize, isize)>,
+    /// Cells of the current leaf not returned yet, and its top left cell.
+    bits: u64,
+    left: isize,
+    top: isize,
+}
+
+impl<'a> LiveCells<'a> {
+    fn new(hashlife: &'a Hashlife, area: Option<BoundingBox>) -> Self {
+        let mut cells = Self {
+            store: &hashlife.store,
+            area,
+            stack: Vec::new(),
+            bits: 0,
+            left: 0,
+            top: 0,
+        };
+        if let Some(top) = hashlife.top {
+            let (left, top_y) = top_corner(hashlife.store.get(top).level);
+            cells.push(top, left, top_y);
+        }
+        cells
+    }
+
+    /// Queue a node unless it has no living cells in the area.
+    fn push(&mut self, node: NodeId, left: isize, top: isize) {
+        let n = self.store.get(node);
+        let side = 1isize << n.level;
+        let bounds = BoundingBox::from(top, top - side + 1, left, left + side - 1);
+        let in_area = match self.area {
+            Some(area) => area.collides(&bounds),
+            None => true,
+        };
+        if n.population > 0 && in_area {
+            self.stack.push((node, left, top));
+        }
+    }
+}
+
+impl Iterator for LiveCells<'_> {
+    type Item = (isize, isize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.bits != 0 {
+                let bit = self.bits.trailing_zeros() as isize;
+                self.bits &= self.bits - 1;
+                return Some((self.left + bit % 8, self.top - bit / 8));
+            }
+            let (node, left, top) = self.stack.pop()?;
+            let n = *self.store.get(node);
+            match n.content {
+                Content::Leaf(bits) => {
+                    let mut inside = !0u64;
+                    if let Some(area) = self.area {
+                        for bit in 0..64 {
+                            if !area.contains(left + bit % 8, top - bit / 8) {
+                                inside &= !(1 << bit);
+                            }
+                        }
+                    }
+                    self.bits = bits & inside;
+                    self.left = left;
+                    self.top = top;
+                },
+                Content::Branch(c) => {
+                    // Pushed in reverse so the north west is visited first.
+                    let half = 1isize << (n.level - 1);
+                    self.push(c.se, left + half, top - half);
+                    self.push(c.sw, left, top - half);
+                    self.push(c.ne, left + half, top);
+                    self.push(c.nw, left, top);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Automata, Edge};
+
+    #[test]
+    fn live_bounds_fit_the_cells() {
+        let cells = vec![
+            0,0,0,0,0,0,
+            0,0,1,0,0,0,
+            0,0,0,0,0,0,
+            0,0,0,0,1,0,
+            0,1,0,0,0,0,
+        ];
+        let mut hashlife = Hashlife::from_array(cells, 6, 5, Edge::Infinite);
+        assert_eq!(hashlife.live_bounds(), Some(BoundingBox::from(1, -2, -2, 1)));
+
+        hashlife.set(-100, 37, Automata::Alive);
+        hashlife.set(20, -300, Automata::Alive);
+        assert_eq!(hashlife.live_bounds(), Some(BoundingBox::from(37, -300, -100, 20)));
+    }
+
+    #[test]
+    fn live_bounds_match_the_live_cells() {
+        for seed in 0..8 {
+            let cells = crate::random(seed).take(24 * 24).map(|n| (n % 5 == 0) as u8).collect();
+            let mut hashlife = Hashlife::from_array(cells, 24, 24, Edge::Infinite);
+            for _ in 0..4 {
+                let (xs, ys): (Vec<_>, Vec<_>) = hashlife.live_cells().unzip();
+                let expected = xs.iter().min().map(|&left| {
+                    let (top, bottom) = (*ys.iter().max().unwrap(), *ys.iter().min().unwrap());
+                    BoundingBox::from(top, bottom, left, *xs.iter().max().unwrap())
+                });
+                assert_eq!(hashlife.live_bounds(), expected, "seed {} generation {}", seed, hashlife.get_generation());
+                hashlife.advance(37);
+            }
+        }
+    }
+
+    #[test]
+    fn live_bounds_follow_a_glider() {
+        let glider = vec![
+            0,1,0,
+            0,0,1,
+            1,1,1,
+        ];
+        let mut hashlife = Hashlife::from_array(glider, 3, 3, Edge::Infinite);
+        assert_eq!(hashlife.live_bounds(), Some(BoundingBox::from(1, -1, -1, 1)));
+        hashlife.advance(400);
+        assert_eq!(hashlife.live_bounds(), Some(BoundingBox::from(-99, -101, 99, 101)));
+    }
+
+    #[test]
+    fn empty_universe_has_no_bounds() {
+        let hashlife = Hashlife::from_array(vec![0; 64], 8, 8, Edge::Torus);
+        assert_eq!(hashlife.live_bounds(), None);
+        let single = Hashlife::from_array(vec![1], 1, 1, Edge::Truncate);
+        assert_eq!(single.live_bounds(), Some(BoundingBox::from(0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn live_cells_match_get() {
+        let cells = crate::random(7).take(40 * 24).map(|n| (n & 1) as u8).collect();
+        let hashlife = Hashlife::from_array(cells, 40, 24, Edge::Infinite);
+        let area = BoundingBox::from(5, -9, -13, 2);
+        let mut all = Vec::new();
+        let mut inside = Vec::new();
+        for x in -32..32 {
+            for y in -32..32 {
+                if hashlife.get(x, y) == Some(Automata::Alive) {
+                    all.push((x, y));
+                    if area.contains(x, y) {
+                        inside.push((x, y));
+                    }
+                }
+            }
+        }
+        let mut live = hashlife.live_cells().collect::<Vec<_>>();
+        live.sort_unstable();
+        assert_eq!(live, all);
+        let mut live = hashlife.live_cells_in(&area).collect::<Vec<_>>();
+        live.sort_unstable();
+        assert_eq!(live, inside);
+    }
+
+    #[test]
+    fn live_cells_skip_empty_space() {
+        let mut hashlife = Hashlife::from_array(vec![0; 4], 2, 2, Edge::Infinite);
+        hashlife.set(-1_000_000, 3, Automata::Alive);
+        hashlife.set(5, -2_000_000, Automata::Alive);
+        assert_eq!(hashlife.live_cells().collect::<Vec<_>>(), vec![(-1_000_000, 3), (5, -2_000_000)]);
+        let area = BoundingBox::from(10, -10, -1_000_000, 10);
+        assert_eq!(hashlife.live_cells_in(&area).collect::<Vec<_>>(), vec![(-1_000_000, 3)]);
+        assert_eq!(Hashlife::from_array(vec![0; 64], 8, 8, Edge::Torus).live_cells().next(), None);
+    }
+
+    #[test]
+    fn population_in_rectangles() {
+        let hashlife = Hashlife::from_array(vec![1; 20 * 12], 20, 12, Edge::Infinite);
+        assert_eq!(hashlife.population(), 240);
+        assert_eq!(hashlife.population_in(&BoundingBox::from(100, -100, -100, 100)), 240);
+        assert_eq!(hashlife.population_in(&BoundingBox::from(2, -3, -7, 0)), 6 * 8);
+        assert_eq!(hashlife.population_in(&BoundingBox::from(0, 0, 0, 0)), 1);
+        assert_eq!(hashlife.population_in(&BoundingBox::from(50, 40, 0, 0)), 0);
+        let live = hashlife.live_cells_in(&BoundingBox::from(9, -1, -3, 30)).count() as u128;
+        assert_eq!(hashlife.population_in(&BoundingBox::from(9, -1, -3, 30)), live);
+    }
+
+    #[test]
+    fn population_counts_past_usize() {
+        // A full leaf repeated over a tree of level 40 has 2^80 cells.
+        let mut hashlife = Hashlife::from_array(vec![1; 64], 8, 8, Edge::Infinite);
+        let mut node = hashlife.top.unwrap();
+        for _ in 3..40 {
+            node = hashlife.join(node, node, node, node);
+        }
+        hashlife.top = Some(node);
+        assert_eq!(hashlife.population(), 1 << 80);
+        assert_eq!(hashlife.population_in(&BoundingBox::from(-1, -8, 0, 7)), 64);
+    }
+}
@@ -0,0 +1,677 @@
+//! The run length encoded pattern format.
+//!
+//! A file starts with `#` lines holding its metadata, then a header such as
+//! `x = 3, y = 3, rule = B3/S23` and the cells, row by row from the top left.
+//! `b` is a dead cell, `o` a living one, `$` ends a row and `!` the pattern.
+//! Any of them can be preceded by a repeat count.
+//!
+//! Files of more than two states write state 0 as `.` and the states from 1
+//! to 24 as the letters from `A` to `X`. Higher states take a prefix from `p`
+//! to `y` counting 24 states each, so `pA` is state 25 and `yO` state 255.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::format::{rows_down, written_area, MAX_SIDE};
+use crate::pattern::centred;
+use crate::rle_loader::MultiStateData;
+use crate::sparse::Runs;
+use crate::{BoundingBox, Hashlife, HashlifeError, Metadata, ParseRuleError, Pattern};
+
+/// Lines of an RLE file are wrapped to this width.
+const LINE_WIDTH: usize = 70;
+
+/// What `write` adds to the cells.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Written as `#N`.
+    pub name: Option<String>,
+    /// Written as `#O`.
+    pub author: Option<String>,
+    /// Written as `#C`, one line each.
+    pub comments: Vec<String>,
+    /// Write a `#P` line with the top left cell, so `read` puts the cells
+    /// back where they were.
+    pub position: bool,
+}
+
+/// The header and metadata of an RLE file.
+pub(crate) struct Parsed {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) metadata: Metadata,
+    /// The rule as written, also when it is not a rule of two states.
+    pub(crate) rule: Option<String>,
+}
+
+impl Parsed {
+    /// Keep a rule as written, dropping the bounded grid suffix Golly adds
+    /// after a `:`. A file of two states must have a rule of two states.
+    fn set_rule(&mut self, text: &str, number: usize, column: usize, alphabet: Alphabet) -> Result<(), HashlifeError> {
+        let text = text.split(':').next().unwrap_or(text).trim();
+        let rule = text.parse().map_err(|err: ParseRuleError| HashlifeError::parse(number, column, &err.to_string()));
+        self.metadata.rule = match alphabet {
+            Alphabet::TwoStates => Some(rule?),
+            Alphabet::MultiState => rule.ok(),
+        };
+        self.rule = Some(text.to_string());
+        Ok(())
+    }
+}
+
+/// The cell states a file is read with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Alphabet {
+    /// Dead and alive, with a rule of two states.
+    TwoStates,
+    /// Any state up to 255, with any rule.
+    MultiState,
+}
+
+impl Alphabet {
+    fn max_state(self) -> usize {
+        match self {
+            Alphabet::TwoStates => 1,
+            Alphabet::MultiState => u8::MAX as usize,
+        }
+    }
+}
+
+/// Read a pattern from an RLE file. The pattern is placed at the position
+/// given by a `#P` or `#R` line, or centred on the origin like
+/// `Pattern::from_array` places its cells.
+///
+/// Whitespace and line breaks may appear anywhere between the cells and
+/// anything after the final `!` is ignored. Cells past the size given in the
+/// header grow the pattern.
+pub fn read(source: &str) -> Result<Pattern, HashlifeError> {
+    read_from(source.as_bytes())
+}
+
+/// Like `read`, reading the file as it goes. Only the runs are kept and the
+/// tree is built from their ends, so a large sparse pattern or a long run
+/// needs memory for the runs only, not for the rectangle around them.
+pub fn read_from<R: Read>(reader: R) -> Result<Pattern, HashlifeError> {
+    let mut runs = Runs::default();
+    let (mut width, mut height) = (0, 0);
+    let parsed = parse(BufReader::new(reader), Alphabet::TwoStates, |row, col, length, _| {
+        runs.insert_run(col, row, length);
+        width = width.max(col + length);
+        height = height.max(row + 1);
+    })?;
+    let (width, height) = (width.max(parsed.width), height.max(parsed.height));
+    let (left, top) = match parsed.metadata.position {
+        Some((x, y)) => (x, -y),
+        None => centred(width, height),
+    };
+    let mut pattern = Pattern::from_runs(width, height, left, top, runs);
+    *pattern.metadata_mut() = parsed.metadata;
+    Ok(pattern)
+}
+
+/// Write the cells of a universe inside a rectangle as an RLE file, along
+/// with its rule. Without a rectangle the smallest one holding every living
+/// cell is written.
+pub fn write<W: Write>(hashlife: &Hashlife, area: Option<&BoundingBox>, writer: &mut W, options: &WriteOptions) -> io::Result<()> {
+    let area = written_area(hashlife, area)?;
+
+    if let Some(name) = &options.name {
+        writeln!(writer, "#N {}", name)?;
+    }
+    if let Some(author) = &options.author {
+        writeln!(writer, "#O {}", author)?;
+    }
+    for comment in &options.comments {
+        writeln!(writer, "#C {}", comment)?;
+    }
+    let area = match area {
+        Some(area) => area,
+        None => return writeln!(writer, "x = 0, y = 0, rule = {}\n!", hashlife.get_rule()),
+    };
+    if options.position {
+        writeln!(writer, "#P {} {}", area.left(), -area.top())?;
+    }
+    writeln!(writer, "x = {}, y = {}, rule = {}", area.width(), area.height(), hashlife.get_rule())?;
+
+    let cells = rows_down(hashlife, &area);
+    let mut lines = Lines { writer, line: String::new() };
+    let (mut row, mut col) = (-area.top(), area.left());
+    let mut run: Option<(isize, usize)> = None;
+    for (y, x) in cells {
+        // A run carries on only on the next cell of the same row.
+        if let Some((start, length)) = run {
+            if y == row && x == start + length as isize {
+                run = Some((start, length + 1));
+                continue;
+            }
+            lines.push(length, "o")?;
+            col = start + length as isize;
+        }
+        if y != row {
+            lines.push((y - row) as usize, "$")?;
+            row = y;
+            col = area.left();
+        }
+        if x > col {
+            lines.push((x - col) as usize, "b")?;
+        }
+        run = Some((x, 1));
+    }
+    if let Some((_, length)) = run {
+        lines.push(length, "o")?;
+    }
+    lines.push(1, "!")?;
+    writeln!(lines.writer, "{}", lines.line)
+}
+
+/// Write the cells of a file of many states, `.` for state 0 and letters for
+/// the others. Fails when the cells are not `width * height` states.
+pub fn write_states<W: Write>(rle: &MultiStateData, writer: &mut W) -> io::Result<()> {
+    let (width, height) = (rle.width as usize, rle.height as usize);
+    let expected = width.saturating_mul(height);
+    if rle.cells.len() != expected {
+        let err = HashlifeError::BufferSize { expected, actual: rle.cells.len() };
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+    }
+    match &rle.rule {
+        Some(rule) => writeln!(writer, "x = {}, y = {}, rule = {}", width, height, rule)?,
+        None => writeln!(writer, "x = {}, y = {}", width, height)?,
+    }
+    let mut lines = Lines { writer, line: String::new() };
+    // The rows ended since the last cell written.
+    let mut ended = 0;
+    for row in rle.cells.chunks(width.max(1)) {
+        let end = row.iter().rposition(|&state| state != 0).map_or(0, |last| last + 1);
+        if end > 0 && ended > 0 {
+            lines.push(ended, "$")?;
+            ended = 0;
+        }
+        let mut col = 0;
+        while col < end {
+            let state = row[col];
+            let length = row[col..end].iter().take_while(|&&other| other == state).count();
+            lines.push(length, &state_tag(state))?;
+            col += length;
+        }
+        ended += 1;
+    }
+    lines.push(1, "!")?;
+    writeln!(lines.writer, "{}", lines.line)
+}
+
+/// The letters of a state in a file of many states.
+fn state_tag(state: u8) -> String {
+    if state == 0 {
+        return ".".to_string();
+    }
+    let (prefix, letter) = ((state - 1) / 24, (b'A' + (state - 1) % 24) as char);
+    match prefix {
+        0 => letter.to_string(),
+        _ => format!("{}{}", (b'o' + prefix) as char, letter),
+    }
+}
+
+/// Wraps the runs of an RLE file so no line is longer than `LINE_WIDTH`.
+struct Lines<'a, W> {
+    writer: &'a mut W,
+    line: String,
+}
+
+impl<W: Write> Lines<'_, W> {
+    fn push(&mut self, count: usize, tag: &str) -> io::Result<()> {
+        let run = if count == 1 { tag.to_string() } else { format!("{}{}", count, tag) };
+        if self.line.len() + run.len() > LINE_WIDTH {
+            writeln!(self.writer, "{}", self.line)?;
+            self.line.clear();
+        }
+        self.line.push_str(&run);
+        Ok(())
+    }
+}
+
+/// The characters of a file, decoded from its bytes as they are read so a
+/// line of cells is never held in memory. `line` and `column` are where the
+/// next character is, counting from 1.
+struct Chars<R> {
+    reader: R,
+    peeked: Option<char>,
+    line: usize,
+    column: usize,
+}
+
+impl<R: BufRead> Chars<R> {
+    fn new(reader: R) -> Self {
+        Chars { reader, peeked: None, line: 1, column: 1 }
+    }
+
+    fn peek(&mut self) -> io::Result<Option<char>> {
+        if self.peeked.is_none() {
+            self.peeked = self.decode()?;
+        }
+        Ok(self.peeked)
+    }
+
+    fn next(&mut self) -> io::Result<Option<char>> {
+        let c = match self.peeked.take() {
+            Some(c) => Some(c),
+            None => self.decode()?,
+        };
+        match c {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            },
+            Some(_) => self.column += 1,
+            None => (),
+        }
+        Ok(c)
+    }
+
+    /// The rest of the line without its line break.
+    fn rest_of_line(&mut self) -> io::Result<String> {
+        let mut text = String::new();
+        while let Some(c) = self.next()? {
+            if c == '\n' {
+                break;
+            }
+            text.push(c);
+        }
+        Ok(text)
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.reader.fill_buf()?.first().copied();
+        if byte.is_some() {
+            self.reader.consume(1);
+        }
+        Ok(byte)
+    }
+
+    fn decode(&mut self) -> io::Result<Option<char>> {
+        let lead = match self.byte()? {
+            Some(lead) if lead.is_ascii() => return Ok(Some(lead as char)),
+            Some(lead) => lead,
+            None => return Ok(None),
+        };
+        let length = match lead {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        let mut bytes = [lead, 0, 0, 0];
+        for byte in bytes.iter_mut().take(length).skip(1) {
+            *byte = self.byte()?.unwrap_or(0);
+        }
+        match std::str::from_utf8(&bytes[..length]) {
+            Ok(text) => Ok(text.chars().next()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")),
+        }
+    }
+}
+
+/// Read an RLE file, handing each run of cells in a state other than 0 to
+/// `run` as a row from the top, a column, a length and the state. The cells
+/// are read a character at a time and nothing after the final `!` is read.
+pub(crate) fn parse<R, F>(reader: R, alphabet: Alphabet, mut run: F) -> Result<Parsed, HashlifeError>
+where
+    R: BufRead,
+    F: FnMut(usize, usize, usize, u8),
+{
+    let mut parsed = Parsed { width: 0, height: 0, metadata: Metadata::default(), rule: None };
+    let mut chars = Chars::new(reader);
+    let mut header_read = false;
+    let (mut row, mut col) = (0usize, 0usize);
+    // The repeat count read so far and where it started.
+    let mut count: Option<(usize, usize, usize)> = None;
+    let mut last = 1;
+
+    'lines: loop {
+        let number = chars.line;
+        // Whitespace at the start of a line is skipped, whatever follows.
+        while let Some(c) = chars.peek()? {
+            if c == '\n' || !c.is_whitespace() {
+                break;
+            }
+            chars.next()?;
+        }
+        let first = chars.column;
+        let c = match chars.peek()? {
+            Some(c) => c,
+            None => {
+                // A last line of whitespace only still counts as a line.
+                if first > 1 {
+                    last = number;
+                }
+                break;
+            },
+        };
+        last = number;
+        if c == '\n' {
+            chars.next()?;
+            continue;
+        }
+        if c == '#' {
+            comment(chars.rest_of_line()?.trim(), number, &mut parsed, alphabet)?;
+            continue;
+        }
+        if !header_read {
+            header(&chars.rest_of_line()?, number, first, &mut parsed, alphabet)?;
+            header_read = true;
+            continue;
+        }
+        // The prefix of a state from `p` and the column it is on.
+        let mut prefix: Option<(usize, usize)> = None;
+        loop {
+            let here = chars.column;
+            let c = match chars.next()? {
+                Some('\n') | None => break,
+                Some(c) => c,
+            };
+            if let (Some((_, at)), false) = (prefix, matches!(c, 'A'..='X')) {
+                return Err(HashlifeError::parse(number, at, "expected a state from `A` to `X` after the prefix"));
+            }
+            let state = match c {
+                '0'..='9' => {
+                    let (n, start_line, start_column) = count.unwrap_or((0, number, here));
+                    let n = n.checked_mul(10).and_then(|n| n.checked_add(c as usize - '0' as usize));
+                    let n = n.filter(|&n| n <= MAX_SIDE).ok_or_else(|| HashlifeError::parse(number, here, "the repeat count is too large"))?;
+                    count = Some((n, start_line, start_column));
+                    continue;
+                },
+                'p'..='y' => {
+                    prefix = Some((c as usize - 'p' as usize + 1, here));
+                    continue;
+                },
+                'b' | '.' => 0,
+                'o' => 1,
+                'A'..='X' => prefix.map_or(0, |(p, _)| p) * 24 + (c as usize - 'A' as usize) + 1,
+                '$' => {
+                    row += count.take().map_or(1, |(n, _, _)| n);
+                    col = 0;
+                    if row > MAX_SIDE {
+                        return Err(HashlifeError::parse(number, here, "too many rows"));
+                    }
+                    continue;
+                },
+                '!' => match count {
+                    Some((_, line, column)) => return Err(HashlifeError::parse(line, column, "a repeat count before the end")),
+                    None => break 'lines,
+                },
+                c if c.is_whitespace() => continue,
+                c => return Err(HashlifeError::parse(number, here, &format!("unexpected character `{}`", c))),
+            };
+            let start = prefix.take().map_or(here, |(_, at)| at);
+            if state > alphabet.max_state() {
+                return Err(HashlifeError::parse(number, start, &format!("state {} is out of range", state)));
+            }
+            let n = count.take().map_or(1, |(n, _, _)| n);
+            if state > 0 && n > 0 {
+                run(row, col, n, state as u8);
+            }
+            col += n;
+            if col > MAX_SIDE {
+                return Err(HashlifeError::parse(number, here, "the row is too long"));
+            }
+        }
+        if let Some((_, at)) = prefix {
+            return Err(HashlifeError::parse(number, at, "expected a state from `A` to `X` after the prefix"));
+        }
+    }
+    if let Some((_, line, column)) = count {
+        return Err(HashlifeError::parse(line, column, "a repeat count without a cell"));
+    }
+    if !header_read {
+        return Err(HashlifeError::parse(last, 1, "missing the `x = .., y = ..` header"));
+    }
+    Ok(parsed)
+}
+
+/// Read a `#` line into the metadata. Unknown lines are skipped.
+fn comment(line: &str, number: usize, parsed: &mut Parsed, alphabet: Alphabet) -> Result<(), HashlifeError> {
+    let metadata = &mut parsed.metadata;
+    let mut chars = line.chars();
+    chars.next();
+    let kind = chars.next();
+    let text = chars.as_str().trim();
+    match kind {
+        Some('N') => metadata.name = Some(text.to_string()),
+        Some('O') => metadata.author = Some(text.to_string()),
+        Some('C') | Some('c') => metadata.comments.push(text.to_string()),
+        Some('P') | Some('R') => {
+            let coordinates = text.split_whitespace().map(|v| v.parse::<isize>()).collect::<Vec<_>>();
+            match coordinates[..] {
+                [Ok(x), Ok(y)] if x.unsigned_abs() <= MAX_SIDE && y.unsigned_abs() <= MAX_SIDE => {
+                    metadata.position = Some((x, y))
+                },
+                _ => return Err(HashlifeError::parse(number, 3, "expected a position as `<x> <y>`")),
+            }
+        },
+        Some('r') => parsed.set_rule(text, number, 3, alphabet)?,
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Read the `x = .., y = .., rule = ..` header, the rule is optional. The
+/// header starts on `column` of its line.
+fn header(line: &str, number: usize, mut column: usize, parsed: &mut Parsed, alphabet: Alphabet) -> Result<(), HashlifeError> {
+    let (mut width, mut height, mut rule_read) = (None, None, false);
+    for part in line.split(',') {
+        let length = part.chars().count();
+        let here = column + length - part.trim_start().chars().count();
+        column += length + 1;
+        let (key, value) = match part.split_once('=') {
+            Some(pair) => pair,
+            // The bounded grid suffix of a rule holds a comma too.
+            None if rule_read => continue,
+            None => return Err(HashlifeError::parse(number, here, "expected `<key> = <value>`")),
+        };
+        let side = || {
+            value.trim().parse::<usize>().ok().filter(|&side| side <= MAX_SIDE).ok_or_else(|| HashlifeError::parse(number, here, "expected a size"))
+        };
+        match key.trim() {
+            "x" => width = Some(side()?),
+            "y" => height = Some(side()?),
+            "rule" => {
+                parsed.set_rule(value, number, here, alphabet)?;
+                rule_read = true;
+            },
+            _ => (),
+        }
+    }
+    match (width, height) {
+        (Some(width), Some(height)) => {
+            parsed.width = width;
+            parsed.height = height;
+            Ok(())
+        },
+        _ => Err(HashlifeError::parse(number, 1, "missing the `x = .., y = ..` header")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Automata, Rule};
+
+    #[test]
+    fn read_metadata_and_cells() {
+        let source = "#N Glider\r\n#O Richard K. Guy\r\n#C The smallest spaceship.\r\n#c Found in 1969.\r\n\
+                      #P 10 -4\r\nx = 3, y = 3, rule = 23/3\r\nbo$2bo$3o!\r\n";
+        let pattern = read(source).unwrap();
+        let metadata = pattern.metadata();
+        assert_eq!(metadata.name.as_deref(), Some("Glider"));
+        assert_eq!(metadata.author.as_deref(), Some("Richard K. Guy"));
+        assert_eq!(metadata.comments, vec!["The smallest spaceship.", "Found in 1969."]);
+        assert_eq!(metadata.rule, Some(Rule::life()));
+        assert_eq!(metadata.position, Some((10, -4)));
+        assert_eq!(*pattern.bounds(), BoundingBox::from(4, 2, 10, 12));
+        for &(x, y) in &[(11, 4), (12, 3), (10, 2), (11, 2), (12, 2)] {
+            assert_eq!(pattern.get(x, y), Automata::Alive);
+        }
+        assert_eq!(pattern.get(10, 4), Automata::Dead);
+    }
+
+    #[test]
+    fn read_tolerates_layout() {
+        let wrapped = read("x = 5, y = 3, rule = B3/S23:T20,20\n2o\n 3b$\n\n5o$2\n\tb3o! trailing text $$ 99q").unwrap();
+        let plain = read("x=5,y=3\n2o3b$5o$2b3o!").unwrap();
+        assert_eq!(wrapped.metadata().rule, Some(Rule::life()));
+        assert_eq!(wrapped.bounds(), plain.bounds());
+        for x in -3..3 {
+            for y in -2..2 {
+                assert_eq!(wrapped.get(x, y), plain.get(x, y), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn read_reports_positions() {
+        let position = |source| match read(source) {
+            Err(HashlifeError::Parse { line, column, .. }) => (line, column),
+            other => panic!("expected a parse error, got {:?}", other.map(|pattern| *pattern.bounds())),
+        };
+        assert_eq!(position("#C only a comment"), (1, 1));
+        assert_eq!(position("x = 3, y = three"), (1, 8));
+        assert_eq!(position("x = 3, y = 1, rule = B9/S23\n3o!"), (1, 15));
+        assert_eq!(position("x = 3, y = 2\n3o$\nbxo!"), (3, 2));
+        assert_eq!(position("x = 3, y = 2\n3o$\n12\n!"), (3, 1));
+        assert_eq!(position("#P 1\nx = 1, y = 1\no!"), (1, 3));
+        assert_eq!(position("x = 3, y = 1\n\u{a0}\u{a0}oq!"), (2, 4));
+        assert_eq!(position("x = 3, y\u{e9} = 1, y = one"), (1, 16));
+        let long = format!("x = 1, y = 1\n{}q!", "o".repeat(300_000));
+        assert_eq!(position(&long), (2, 300_001));
+    }
+
+    fn written(hashlife: &Hashlife, area: Option<&BoundingBox>, options: &WriteOptions) -> String {
+        let mut out = Vec::new();
+        write(hashlife, area, &mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn write_compresses_rows() {
+        let cells = vec![
+            0,1,1,1,0,0,
+            0,0,0,0,0,0,
+            0,0,0,0,0,0,
+            1,0,0,0,0,1,
+        ];
+        let hashlife = Hashlife::from_array(cells, 6, 4, crate::Edge::Infinite);
+        let options = WriteOptions {
+            name: Some("Sample".to_string()),
+            author: Some("Someone".to_string()),
+            comments: vec!["first".to_string(), "second".to_string()],
+            position: true,
+        };
+        assert_eq!(
+            written(&hashlife, None, &options),
+            "#N Sample\n#O Someone\n#C first\n#C second\n#P -3 -1\nx = 6, y = 4, rule = B3/S23\nb3o3$o4bo!\n"
+        );
+        let area = BoundingBox::from(3, -3, -5, 4);
+        assert_eq!(written(&hashlife, Some(&area), &WriteOptions::default()), "x = 10, y = 7, rule = B3/S23\n2$3b3o3$2bo4bo!\n");
+        let empty = Hashlife::from_array(vec![0; 4], 2, 2, crate::Edge::Torus);
+        assert_eq!(written(&empty, None, &WriteOptions::default()), "x = 0, y = 0, rule = B3/S23\n!\n");
+    }
+
+    #[test]
+    fn write_round_trips_through_read() {
+        let cells = crate::random(11).take(150 * 40).map(|n| (n % 3 == 0) as u8).collect();
+        let mut hashlife = Hashlife::from_array(cells, 150, 40, crate::Edge::Infinite);
+        hashlife.set_rule("B36/S23".parse().unwrap());
+        let options = WriteOptions { position: true, ..WriteOptions::default() };
+        let text = written(&hashlife, None, &options);
+        assert!(text.lines().all(|line| line.len() <= LINE_WIDTH));
+
+        let pattern = read(&text).unwrap();
+        assert_eq!(pattern.metadata().rule, Some("B36/S23".parse().unwrap()));
+        assert_eq!(Some(*pattern.bounds()), hashlife.live_bounds());
+        for x in -80..80 {
+            for y in -25..25 {
+                assert_eq!(Some(pattern.get(x, y)), hashlife.get(x, y), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn write_wraps_around_a_torus() {
+        let mut hashlife = Hashlife::from_array(vec![0; 64], 8, 8, crate::Edge::Torus);
+        hashlife.set(3, 0, Automata::Alive);
+        let area = BoundingBox::from(0, 0, 2, 12);
+        assert_eq!(written(&hashlife, Some(&area), &WriteOptions::default()), "x = 11, y = 1, rule = B3/S23\nbo7bo!\n");
+    }
+
+    struct Failing;
+
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "read past the end"))
+        }
+    }
+
+    #[test]
+    fn read_from_streams_sparse_patterns() {
+        let source = b"#C Two blinkers far apart.\nx = 100000, y = 100000\n3o$\n99998$99997b3o!\nnever read \xff";
+        let pattern = read_from(&source[..]).unwrap();
+        assert_eq!(*pattern.bounds(), BoundingBox::from(49999, -50000, -50000, 49999));
+        assert_eq!(pattern.metadata().comments, vec!["Two blinkers far apart."]);
+        for &(x, y) in &[(-50000, 49999), (-49998, 49999), (49997, -50000), (49999, -50000)] {
+            assert_eq!(pattern.get(x, y), Automata::Alive, "({}, {})", x, y);
+        }
+        assert_eq!(pattern.get(-49997, 49999), Automata::Dead);
+
+        // Cells on a single line of a megabyte, then a reader that fails.
+        let line = io::repeat(b'o').take(1 << 20).chain(io::repeat(b'b').take(1 << 20));
+        let reader = (&b"x = 0, y = 0\n"[..]).chain(line).chain(&b"o!"[..]).chain(Failing);
+        let pattern = read_from(reader).unwrap();
+        assert_eq!(pattern.width(), (1 << 21) + 1);
+        let left = pattern.bounds().left();
+        assert_eq!(pattern.get(left + (1 << 20) - 1, 0), Automata::Alive);
+        assert_eq!(pattern.get(left + (1 << 20), 0), Automata::Dead);
+        assert_eq!(pattern.get(left + (1 << 21), 0), Automata::Alive);
+
+                let invalid = read_from(&b"x = 1, y = 1\n\xffo!"[..]);
+        assert!(matches!(invalid, Err(HashlifeError::Io { kind: io::ErrorKind::InvalidData, .. })));
+    }
+
+    #[test]
+    fn read_long_runs() {
+        let pattern = read("x = 1, y = 1\n2000000000o!").unwrap();
+        assert_eq!(pattern.width(), 2_000_000_000);
+        let (left, top) = (pattern.bounds().left(), pattern.bounds().top());
+        assert_eq!(pattern.get(left, top), Automata::Alive);
+        assert_eq!(pattern.get(left + 1_999_999_999, top), Automata::Alive);
+        assert_eq!(pattern.get(left + 2_000_000_000, top), Automata::Dead);
+
+        let pattern = read(&format!("#P 0 0\nx = 1, y = 1\n{}o$3b{}o!", 1u64 << 60, (1u64 << 60) - 3)).unwrap();
+        assert_eq!(*pattern.bounds(), BoundingBox::from(0, -1, 0, (1 << 60) - 1));
+        assert_eq!(pattern.get(2, -1), Automata::Dead);
+        assert_eq!(pattern.get(1 << 59, -1), Automata::Alive);
+    }
+
+    #[test]
+    fn write_states_round_trips() {
+        let mut cells = vec![0; 30 * 4];
+        cells[..4].copy_from_slice(&[1, 1, 24, 25]);
+        cells[60..66].copy_from_slice(&[0, 0, 48, 49, 255, 2]);
+        cells[119] = 3;
+        let rle = MultiStateData { width: 30, height: 4, rule: Some("23/3/3".to_string()), cells };
+        let mut out = Vec::new();
+        write_states(&rle, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text, "x = 30, y = 4, rule = 23/3/3\n2AXpA2$2.pXqAyOB$29.C!\n");
+
+        let copy = crate::rle_loader::try_load_states(&text).unwrap();
+        assert_eq!((copy.width, copy.height, &copy.rule, &copy.cells), (rle.width, rle.height, &rle.rule, &rle.cells));
+
+        let short = MultiStateData { width: 2, height: 2, rule: None, cells: vec![1] };
+        assert_eq!(write_states(&short, &mut Vec::new()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn cells_past_the_header_grow_the_pattern() {
+        let pattern = read("x = 2, y = 1\n4o$o!").unwrap();
+        assert_eq!((pattern.width(), pattern.height()), (4, 2));
+        assert_eq!(pattern.get(pattern.bounds().right(), pattern.bounds().top()), Automata::Alive);
+    }
+}
@@ -1,7 +1,10 @@
 use itertools::Itertools;
-use regex::Regex;
+
+use std::convert::TryFrom;
 
 use crate::automata::Automata;
+use crate::rle::{self, Alphabet, Parsed};
+use crate::HashlifeError;
 
 pub struct RleData {
     pub width: u32,
@@ -9,6 +12,17 @@ pub struct RleData {
     pub cells: Vec<Automata   >,
 }
 
+/// The cells of an RLE file of any number of states.
+pub struct MultiStateData {
+    pub width: u32,
+    pub height: u32,
+    /// The rule as written in the header, which may not be a rule of two
+    /// states.
+    pub rule: Option<String>,
+    /// The state of every cell, row by row from the top left.
+    pub cells: Vec<u8>,
+}
+
 #[derive(Eq, PartialEq, Debug)]
 enum RleCharacter {
     Number,
@@ -36,132 +50,84 @@ enum RleElement {
 }
 
 
+/// Read the cells of an RLE file.
+///
+/// Panics if the file is malformed, see `try_load`.
 pub fn load(rle_string: &str) -> RleData {
-    let re = Regex::new(r"x\s*=\s*(\d+)\s*,\s*y\s*=\s*(\d+)")
-        .expect("Regex failed to compile");
-
-    // The iterator starts here
-    let mut iterator_to_xy_line = rle_string
-        .lines()
-        .map(|line| line.trim_left())
-        // remove comment lines
-        .filter(|line| ! line.starts_with("#"))
-        // remove empty lines
-        .filter(|line| line.trim().len() != 0);
-    
-    let xy_line = iterator_to_xy_line.next().expect("cannot read rle source");
+    try_load(rle_string).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    // collect the width and height
-    let cap = re.captures(xy_line).expect("Rle string input is not valid");
-    let x = String::from(cap.get(1).unwrap().as_str());
-    let y = String::from(cap.get(2).unwrap().as_str());
-    let width_from_file: u32 = x.parse().expect(&format!("error parsing x (`{}`)", x));
-    let height_from_file: u32 = y.parse().expect(&format!("error parsing y (`{}`)", y));
+/// Read the cells of an RLE file, failing with the position of the error
+/// when it is malformed. Runs past the declared width and rows past the
+/// declared height are cut off. Every cell is stored, `rle::read_from` reads
+/// large sparse patterns without doing so.
+pub fn try_load(rle_string: &str) -> Result<RleData, HashlifeError> {
+    let mut runs = Vec::new();
+    let parsed = rle::parse(rle_string.as_bytes(), Alphabet::TwoStates, |row, col, length, _| {
+        runs.push((row, col, length, Automata::Alive))
+    })?;
+    let (width, height, cells) = dense(&parsed, runs, Automata::Dead)?;
+    Ok(RleData {
+        width,
+        height,
+        cells,
+    })
+}
 
-    let mut cells = iterator_to_xy_line
-        // Convert to chars
-        .flat_map(|line| line.chars())
-        .into_iter()
-        // Identify adjacent tokens and group them together
-        .group_by(|c| match *c {
-            'b' => RleCharacter::Dead,
-            'o' => RleCharacter::Alive,
-            '$' => RleCharacter::NewLine,
-            '!' => RleCharacter::End,
-             _  => RleCharacter::Number,
-        })
-        .into_iter()
-        // Convert characters into tokens (particularly Number)
-        .map(|(key, group)| {
-            match key {
-                RleCharacter::Dead => RleToken::Dead,
-                RleCharacter::Alive => RleToken::Alive,
-                RleCharacter::NewLine => RleToken::NewLine,
-                RleCharacter::End => RleToken::End,
-                RleCharacter::Number => RleToken::Number(group.collect::<String>().parse::<u32>().unwrap()),
-            }
-        })
-        // A super simple parser
-        .batching(|it| {
-            match it.next() {
-                None => None,
-                Some(RleToken::Dead) => Some(RleElement::Dead(1)),
-                Some(RleToken::Alive) => Some(RleElement::Alive(1)),
-                Some(RleToken::NewLine) => Some(RleElement::NewLine(1)),
-                Some(RleToken::End) => Some(RleElement::End),
-                Some(RleToken::Number(x)) => {
-                    match it.next() {
-                        None => None,
-                        Some(RleToken::Dead) => Some(RleElement::Dead(x)),
-                        Some(RleToken::Alive) => Some(RleElement::Alive(x)),
-                        Some(RleToken::NewLine) => Some(RleElement::NewLine(x)),
-                        Some(RleToken::Number(_)) => panic!("have a number after a number."),
-                        Some(RleToken::End) => panic!("cannot have multiple ends"),
-                    }
-                }
-            }
-        })
-        // Split up by lines
-        .collect::<Vec<RleElement>>()
-        .into_iter()
-        .group_by(|key| match key {
-            RleElement::NewLine(_) | RleElement::End => false,
-            _ => true
-        })
-        .into_iter()
-        // Oranise into (line, newline_or_end)
-        .map(|(_, group)| {
-            group.collect::<Vec<RleElement>>()
-        })
-        .chunks(2)
-        .into_iter()
-        .map(|mut it| {
-            let line = it.next().expect("No next iterator");
-            let new_line_or_end = it
-                .next()
-                .expect("No next iterator")
-                .get(0)
-                .expect("Missing newline element")
-                .clone();
-            (line, new_line_or_end)
-        })
-        // Convert from RleElements to Vec<Automata>
-        .map(|(line, newline_or_end): (Vec<RleElement> ,RleElement)| {
-            let complete_line = construct_line(line, width_from_file);
-            let newlines = if let RleElement::NewLine(x) = newline_or_end {
-                vec![ Automata::Dead; ((x-1) * width_from_file) as usize]
-            } else { vec![] };
-            let mut section = Vec::new();
-            section.extend(complete_line);
-            section.extend(newlines);
-            section
-        })
-        .flatten()
-        // Expand
-        .collect::<Vec<Automata>>()
-    ;
-    cells.extend(vec![ Automata::Dead; (width_from_file * height_from_file) as usize - cells.len()]);
+/// Read the states of an RLE file of any number of states.
+///
+/// Panics if the file is malformed, see `try_load_states`.
+pub fn load_states(rle_string: &str) -> MultiStateData {
+    try_load_states(rle_string).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    RleData {
-        width: width_from_file,
-        height: height_from_file,
+/// Read the states of an RLE file of any number of states, written with
+/// `.` and the letters from `A` as well as `b` and `o`. The rule is kept as
+/// written, like the cells it is cut to the declared size.
+pub fn try_load_states(rle_string: &str) -> Result<MultiStateData, HashlifeError> {
+    let mut runs = Vec::new();
+    let parsed = rle::parse(rle_string.as_bytes(), Alphabet::MultiState, |row, col, length, state| {
+        runs.push((row, col, length, state))
+    })?;
+    let (width, height, cells) = dense(&parsed, runs, 0)?;
+    Ok(MultiStateData {
+        width,
+        height,
+        rule: parsed.rule,
         cells,
+    })
+}
+
+/// Lay runs of cells out row by row in the declared size of a file.
+fn dense<T: Copy>(parsed: &Parsed, runs: Vec<(usize, usize, usize, T)>, dead: T) -> Result<(u32, u32, Vec<T>), HashlifeError> {
+    let width = u32::try_from(parsed.width).map_err(|_| HashlifeError::TooLarge)?;
+    let height = u32::try_from(parsed.height).map_err(|_| HashlifeError::TooLarge)?;
+    let count = parsed.width.checked_mul(parsed.height).ok_or(HashlifeError::TooLarge)?;
+    let mut cells = Vec::new();
+    cells.try_reserve_exact(count).map_err(|_| HashlifeError::TooLarge)?;
+    cells.resize(count, dead);
+    for (row, col, length, state) in runs {
+        if row < parsed.height && col < parsed.width {
+            let start = row * parsed.width + col;
+            let end = start + length.min(parsed.width - col);
+            cells[start..end].iter_mut().for_each(|cell| *cell = state);
+        }
     }
+    Ok((width, height, cells))
 }
 
 fn construct_line(line: Vec<RleElement>, width: u32) -> Vec<Automata> {
     let width = width as usize;
     let mut line = line
         .into_iter()
-        .map(|element| match element {
+        .flat_map(|element| match element {
             RleElement::Alive(x) => vec![ Automata::Alive; x as usize],
             RleElement::Dead(x) => vec![ Automata::Dead; x as usize],
             _ => panic!("invalid line"),
         })
-        .flatten()
         .collect::<Vec<Automata>>();
     if line.len() < width {
-        let missing_cells = vec![ Automata::Dead; width as usize - line.len()];
+        let missing_cells = vec![ Automata::Dead; width - line.len()];
         line.extend(missing_cells);
         line
     } else {
@@ -174,13 +140,12 @@ pub fn load_spaceships(width: u32, height: u32) -> Vec<Automata> {
 
     let xy_line = raw_contents
         .lines()
-        .skip(1)
-        .next()
+        .nth(1)
         .expect("cannot read ship source.");
     let x: String = xy_line.chars().skip(4).take(3).collect();
-    let width_from_file: u32 = x.parse().expect(&format!("x (`{}`) is not a number", x));
+    let width_from_file: u32 = x.parse().unwrap_or_else(|_| panic!("x (`{}`) is not a number", x));
     let y: String = xy_line.chars().skip(13).take(3).collect();
-    let height_from_file: u32 = y.parse().expect(&format!("y (`{}`) is not a number", y));
+    let height_from_file: u32 = y.parse().unwrap_or_else(|_| panic!("y (`{}`) is not a number", y));
     // unsafe {
     //     log!("width_from_file: {}", width_from_file);
     //     log!("height_from_file: {}", height_from_file);
@@ -188,8 +153,8 @@ pub fn load_spaceships(width: u32, height: u32) -> Vec<Automata> {
     //     log!("height: {}", height);
     // }
 
-    let width_of_grid = if width > width_from_file { width } else { width_from_file };
-    let height_of_grid = if height > height_from_file { height } else { height_from_file };
+    let _width_of_grid = if width > width_from_file { width } else { width_from_file };
+    let _height_of_grid = if height > height_from_file { height } else { height_from_file };
     println!("width: {}, height: {}", width, height);
 
     
@@ -243,10 +208,7 @@ pub fn load_spaceships(width: u32, height: u32) -> Vec<Automata> {
         // Split up by lines
         .collect::<Vec<RleElement>>()
         .into_iter()
-        .group_by(|key| match key {
-            RleElement::NewLine(_) | RleElement::End => false,
-            _ => true
-        })
+        .group_by(|key| !matches!(key, RleElement::NewLine(_) | RleElement::End))
         .into_iter()
         // Oranise into (line, newline_or_end)
         .map(|(_, group)| {
@@ -256,16 +218,15 @@ pub fn load_spaceships(width: u32, height: u32) -> Vec<Automata> {
         .into_iter()
         .map(|mut it| {
             let line = it.next().expect("No next iterator");
-            let new_line_or_end = it
+            let new_line_or_end = *it
                 .next()
                 .expect("No next iterator")
-                .get(0)
-                .expect("Missing newline element")
-                .clone();
+                .first()
+                .expect("Missing newline element");
             (line, new_line_or_end)
         })
         // Convert from RleElements to Vec<Cell>
-        .map(|(line, newline_or_end): (Vec<RleElement> ,RleElement)| {
+        .flat_map(|(line, newline_or_end): (Vec<RleElement> ,RleElement)| {
             let complete_line = construct_line(line, width);
             let newlines = if let RleElement::NewLine(x) = newline_or_end {
                 vec![ Automata::Dead; ((x-1) * width) as usize]
@@ -275,7 +236,6 @@ pub fn load_spaceships(width: u32, height: u32) -> Vec<Automata> {
             section.extend(newlines);
             section
         })
-        .flatten()
         // Truncate
         .take((width * height) as usize)
         // Expand
@@ -283,4 +243,60 @@ pub fn load_spaceships(width: u32, height: u32) -> Vec<Automata> {
     ;
     stream.extend(vec![ Automata::Dead; (width * height) as usize - stream.len()]);
     stream
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_load_reads_cells() {
+        let rle = try_load("#N Glider\nx = 3, y = 4, rule = B3/S23\nbo$2bo$\n3o$!").unwrap();
+        assert_eq!((rle.width, rle.height), (3, 4));
+        let cells = rle.cells.iter().map(|&cell| cell as u8).collect::<Vec<_>>();
+        assert_eq!(cells, vec![0, 1, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn try_load_reports_malformed_files() {
+        let parse = |source| match try_load(source) {
+            Err(HashlifeError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other.map(|rle| rle.cells)),
+        };
+        assert_eq!(parse(""), 1);
+        assert_eq!(parse("#C comment\nx = 3\n3o!"), 2);
+        assert_eq!(parse("x = 3, y = 1\n\n3q!"), 3);
+        assert_eq!(parse("x = 3, y = 1\n99999999999999999999o!"), 2);
+        assert_eq!(parse("x = 3, y = 1\n3o2!"), 2);
+        assert_eq!(parse("x = 3, y = 1\n3o2"), 2);
+        assert_eq!(try_load("x = 4294967295, y = 4294967295\n!").err(), Some(HashlifeError::TooLarge));
+    }
+
+    #[test]
+    fn try_load_states_reads_every_state() {
+        let rle = try_load_states("#C Generations\nx = 4, y = 3, rule = 23/34/5\n.A2B$pAyO\n$3o!").unwrap();
+        assert_eq!((rle.width, rle.height), (4, 3));
+        assert_eq!(rle.rule.as_deref(), Some("23/34/5"));
+        assert_eq!(rle.cells, vec![0, 1, 2, 2, 25, 255, 0, 0, 1, 1, 1, 0]);
+
+        let life = try_load_states("x = 2, y = 1, rule = B3/S23:P20,20\nAb!").unwrap();
+        assert_eq!(life.rule.as_deref(), Some("B3/S23"));
+        assert_eq!(life.cells, vec![1, 0]);
+
+        let position = |source| match try_load_states(source) {
+            Err(HashlifeError::Parse { line, column, .. }) => (line, column),
+            other => panic!("expected a parse error, got {:?}", other.map(|rle| rle.cells)),
+        };
+        assert_eq!(position("x = 2, y = 1\nAyP!"), (2, 2));
+        assert_eq!(position("x = 2, y = 1\nApb!"), (2, 2));
+        assert_eq!(position("x = 2, y = 1\nAq\nA!"), (2, 2));
+        assert_eq!(position("x = 2, y = 1\nAZ!"), (2, 2));
+    }
+
+    #[test]
+    fn two_state_loading_rejects_higher_states() {
+        assert_eq!(try_load("x = 3, y = 1\n.AB!").err(), Some(HashlifeError::parse(2, 3, "state 2 is out of range")));
+        assert!(matches!(try_load("x = 1, y = 1, rule = 23/3/3\nA!"), Err(HashlifeError::Parse { line: 1, column: 15, .. })));
+        assert_eq!(try_load("x = 3, y = 1\n.AA!").unwrap().cells, vec![Automata::Dead, Automata::Alive, Automata::Alive]);
+    }
+}
@@ -0,0 +1,247 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A Life-like rule: the numbers of living neighbours that bring a dead cell
+/// to life (birth) and that keep a living cell alive (survival).
+///
+/// Rules are parsed from the usual notations, `B3/S23` or the older
+/// survival/birth form `23/3`.
+///
+/// Rules with `B0` are not supported: they turn the infinite empty space
+/// alive, which the quadtree cannot represent.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rule {
+    birth: Vec<usize>,
+    survival: Vec<usize>,
+}
+
+impl Rule {
+    /// Create a rule from its birth and survival neighbour counts.
+    pub fn new(birth: &[usize], survival: &[usize]) -> Self {
+        Self::try_new(birth, survival).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `new`, but fails on `B0` and on counts above 8.
+    pub fn try_new(birth: &[usize], survival: &[usize]) -> Result<Self, ParseRuleError> {
+        let error = |reason| {
+            let digits = |counts: &[usize]| counts.iter().map(|n| n.to_string()).collect::<String>();
+            Err(ParseRuleError { rule: format!("B{}/S{}", digits(birth), digits(survival)), reason })
+        };
+        if birth.contains(&0) {
+            return error("B0 rules are not supported");
+        }
+        if !birth.iter().chain(survival).all(|&n| n <= 8) {
+            return error("neighbour counts must be digits from 0 to 8");
+        }
+        let normalise = |counts: &[usize]| {
+            let mut counts = counts.to_vec();
+            counts.sort_unstable();
+            counts.dedup();
+            counts
+        };
+        Ok(Self {
+            birth: normalise(birth),
+            survival: normalise(survival),
+        })
+    }
+
+    /// Conway's Game of Life, `B3/S23`.
+    pub fn life() -> Self {
+        Self::new(&[3], &[2, 3])
+    }
+
+    pub fn birth(&self) -> &[usize] {
+        &self.birth
+    }
+
+    pub fn survival(&self) -> &[usize] {
+        &self.survival
+    }
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self::life()
+    }
+}
+
+/// Every 4x4 block of cells mapped to its centre 2x2 cells one generation
+/// later under a rule. Blocks pack the cell on row `r` and column `c` into
+/// bit `4r + c`, results pack the centre cells the same way from the top
+/// left: nw, ne, sw then se.
+pub(crate) struct Transitions {
+    table: Vec<u8>,
+}
+
+impl Transitions {
+    pub(crate) fn new(rule: &Rule) -> Self {
+        let mask = |counts: &[usize]| counts.iter().fold(0u16, |mask, &n| mask | 1 << n);
+        let birth = mask(&rule.birth);
+        let survival = mask(&rule.survival);
+        let table = (0..=u16::MAX)
+            .map(|block| {
+                [5, 6, 9, 10].iter().enumerate().fold(0, |result, (i, &center)| {
+                    // The eight cells around the centre cell at bit 5.
+                    let neighborhood = 0b0111_0101_0111u16 << (center - 5);
+                    let neighbors = (block & neighborhood).count_ones();
+                    let counts = if block >> center & 1 == 1 { survival } else { birth };
+                    result | ((counts >> neighbors & 1) as u8) << i
+                })
+            })
+            .collect();
+        Self { table }
+    }
+
+    pub(crate) fn get(&self, block: u16) -> u8 {
+        self.table[block as usize]
+    }
+
+    /// Advance a square of `size` rows by one generation, where bit `c` of a
+    /// row is the cell on column `c`. The result loses the outer ring of
+    /// cells and is `size - 2` rows high. The 4x4 blocks overlap by two
+    /// cells so each lookup fills a 2x2 square of the result.
+    pub(crate) fn generation(&self, rows: &[u16; 16], size: usize) -> [u16; 16] {
+        let mut next = [0u16; 16];
+        for y in (0..size - 2).step_by(2) {
+            for x in (0..size - 2).step_by(2) {
+                let block = (0..4).fold(0, |block, row| block | (rows[y + row] >> x & 0xf) << (row * 4));
+                let result = u16::from(self.get(block));
+                next[y] |= (result & 0b11) << x;
+                next[y + 1] |= (result >> 2 & 0b11) << x;
+            }
+        }
+        next
+    }
+}
+
+/// Error returned when a rule string cannot be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseRuleError {
+    rule: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid rule `{}`: {}", self.rule, self.reason)
+    }
+}
+
+impl Error for ParseRuleError {}
+
+impl FromStr for Rule {
+    type Err = ParseRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |reason| ParseRuleError { rule: s.to_string(), reason };
+        let digits = |part: &str| {
+            part.chars()
+                .map(|c| match c.to_digit(10) {
+                    Some(n) if n <= 8 => Ok(n as usize),
+                    _ => Err(error("neighbour counts must be digits from 0 to 8")),
+                })
+                .collect::<Result<Vec<usize>, ParseRuleError>>()
+        };
+
+        let rule = s.trim();
+        let (first, second) = match rule.find('/') {
+            Some(slash) => (&rule[..slash], &rule[slash + 1..]),
+            None => match rule.find(['S', 's']) {
+                Some(s) => (&rule[..s], &rule[s..]),
+                None => return Err(error("expected `B<birth>/S<survival>` or `<survival>/<birth>`")),
+            },
+        };
+        fn strip(part: &str, prefix: char) -> Option<&str> {
+            part.strip_prefix(prefix)
+                .or_else(|| part.strip_prefix(prefix.to_ascii_lowercase()))
+        }
+        let (birth, survival) = match (strip(first, 'B'), strip(second, 'S')) {
+            (Some(birth), Some(survival)) => (birth, survival),
+            // The survival/birth order is reversed in the prefixed notation.
+            _ => match (strip(first, 'S'), strip(second, 'B')) {
+                (Some(survival), Some(birth)) => (birth, survival),
+                _ if first.chars().chain(second.chars()).all(|c| c.is_ascii_digit()) => (second, first),
+                _ => return Err(error("expected `B<birth>/S<survival>` or `<survival>/<birth>`")),
+            },
+        };
+
+        let birth = digits(birth)?;
+        let survival = digits(survival)?;
+        Self::try_new(&birth, &survival).map_err(|err| error(err.reason))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "B")?;
+        for n in &self.birth {
+            write!(f, "{}", n)?;
+        }
+        write!(f, "/S")?;
+        for n in &self.survival {
+            write!(f, "{}", n)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_birth_survival() {
+        let rule: Rule = "B36/S23".parse().unwrap();
+        assert_eq!(rule.birth(), &[3, 6]);
+        assert_eq!(rule.survival(), &[2, 3]);
+        assert_eq!("b3/s012345678".parse::<Rule>().unwrap(), Rule::new(&[3], &[0, 1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!("B2/S".parse::<Rule>().unwrap(), Rule::new(&[2], &[]));
+        assert_eq!("B3S23".parse::<Rule>().unwrap(), Rule::life());
+        assert_eq!("S23/B3".parse::<Rule>().unwrap(), Rule::life());
+    }
+
+    #[test]
+    fn parse_survival_birth() {
+        assert_eq!("23/3".parse::<Rule>().unwrap(), Rule::life());
+        assert_eq!("23/36".parse::<Rule>().unwrap(), Rule::new(&[3, 6], &[2, 3]));
+        assert_eq!("/2".parse::<Rule>().unwrap(), Rule::new(&[2], &[]));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!("".parse::<Rule>().is_err());
+        assert!("B39/S23".parse::<Rule>().is_err());
+        assert!("B3/X23".parse::<Rule>().is_err());
+        assert!("life".parse::<Rule>().is_err());
+        assert!("B03/S23".parse::<Rule>().is_err());
+        assert!("23/03".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn try_new_rejects_invalid_counts() {
+        assert_eq!(Rule::try_new(&[3], &[2, 3]), Ok(Rule::life()));
+        assert_eq!(Rule::try_new(&[0, 3], &[]).unwrap_err().to_string(), "invalid rule `B03/S`: B0 rules are not supported");
+        assert!(Rule::try_new(&[3], &[9]).is_err());
+    }
+
+    #[test]
+    fn transitions() {
+        let life = Transitions::new(&Rule::life());
+        // A horizontal blinker on the second row turns vertical.
+        assert_eq!(life.get(0b0000_0000_1110_0000), 0b1010);
+        assert_eq!(life.get(0b0000_0000_0111_0000), 0b0101);
+        assert_eq!(life.get(0), 0);
+        assert_eq!(life.get(u16::MAX), 0);
+        let seeds = Transitions::new(&"B2/S".parse().unwrap());
+        assert_eq!(seeds.get(0b0000_0000_0110_0000), 0b1100);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Rule::default().to_string(), "B3/S23");
+        let day_and_night: Rule = "34678/3678".parse().unwrap();
+        assert_eq!(day_and_night.to_string(), "B3678/S34678");
+        assert_eq!(day_and_night.to_string().parse::<Rule>().unwrap(), day_and_night);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use crate::store::LEAF_LEVEL;
+use crate::{Hashlife, NodeId};
+
+/// A run as its row, its first column and the column after it.
+type Run = (usize, usize, usize);
+
+/// Runs of living cells along rows, at columns to the right and rows down
+/// from a top left corner. Only the runs are stored, so the memory used
+/// follows the cells read rather than the area around them.
+#[derive(Default)]
+pub(crate) struct Runs {
+    runs: Vec<Run>,
+}
+
+impl Runs {
+    pub(crate) fn insert(&mut self, col: usize, row: usize) {
+        self.insert_run(col, row, 1);
+    }
+
+    /// Make `length` cells alive from `col` to the right.
+    pub(crate) fn insert_run(&mut self, col: usize, row: usize, length: usize) {
+        if length > 0 {
+            self.runs.push((row, col, col + length));
+        }
+    }
+}
+
+impl Hashlife {
+    /// Build a tree holding the runs with their top left corner at (0, -1),
+    /// the top left cell of its south east quadrant. The work follows the
+    /// ends of the runs rather than their length, see `construct_runs`.
+    pub(crate) fn construct_from_runs(&mut self, runs: Runs) -> NodeId {
+        let extent = runs.runs.iter().map(|&(row, _, end)| end.max(row + 1)).max().unwrap_or(0);
+        let mut level = LEAF_LEVEL;
+        while 1usize << level < extent {
+            level += 1;
+        }
+        let quadrant = self.construct_runs(level, 0, 0, runs.runs, &mut HashMap::new());
+        let e = self.empty(level);
+        self.join(e, e, e, quadrant)
+    }
+
+    /// The node of the given level with its top left cell at column `left`
+    /// and row `top`, holding runs that lie inside of it. Nodes that every
+    /// run crosses from side to side only depend on the rows of the runs, so
+    /// they are built once for each level and set of rows. Only the nodes
+    /// holding an end of a run are split further.
+    fn construct_runs(&mut self, level: usize, left: usize, top: usize, runs: Vec<Run>, crossed: &mut HashMap<(usize, Vec<usize>), NodeId>) -> NodeId {
+        if runs.is_empty() {
+            return self.empty(level);
+        }
+        if level == LEAF_LEVEL {
+            let bits = runs.iter().fold(0u64, |bits, &(row, start, end)| {
+                bits | ((1u64 << (end - start)) - 1) << (start - left) << ((row - top) * 8)
+            });
+            return self.store.leaf(level, bits);
+        }
+        let (side, half) = (1 << level, 1 << (level - 1));
+        let key = if runs.iter().all(|&(_, start, end)| start == left && end == left + side) {
+            let mut rows = runs.iter().map(|&(row, _, _)| row - top).collect::<Vec<_>>();
+            rows.sort_unstable();
+            rows.dedup();
+            if let Some(&node) = crossed.get(&(level, rows.clone())) {
+                return node;
+            }
+            Some((level, rows))
+        } else {
+            None
+        };
+
+        // North west, north east, south west then south east.
+        let mut quadrants = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+        for (row, start, end) in runs {
+            let south = (row >= top + half) as usize * 2;
+            if start < left + half {
+                quadrants[south].push((row, start, end.min(left + half)));
+            }
+            if end > left + half {
+                quadrants[south + 1].push((row, start.max(left + half), end));
+            }
+        }
+        let [nw, ne, sw, se] = quadrants;
+        let nw = self.construct_runs(level - 1, left, top, nw, crossed);
+        let ne = self.construct_runs(level - 1, left + half, top, ne, crossed);
+        let sw = self.construct_runs(level - 1, left, top + half, sw, crossed);
+        let se = self.construct_runs(level - 1, left + half, top + half, se, crossed);
+        let node = self.join(nw, ne, sw, se);
+        if let Some(key) = key {
+            crossed.insert(key, node);
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Automata;
+
+    #[test]
+    fn construct_matches_set() {
+        let runs = [(0, 0, 1), (3, 0, 12), (7, 9, 2), (30, 17, 1), (0, 40, 70), (8, 3, 40)];
+        let mut sparse = Runs::default();
+        let mut expected = Hashlife::new();
+        expected.top = Some(expected.empty(0));
+        for &(col, row, length) in &runs {
+            sparse.insert_run(col, row, length);
+            for col in col..col + length {
+                expected.set(col as isize, -1 - row as isize, Automata::Alive);
+            }
+        }
+        sparse.insert(100, 2);
+        sparse.insert(100, 2);
+        expected.set(100, -3, Automata::Alive);
+
+        let mut hashlife = Hashlife::new();
+        let top = hashlife.construct_from_runs(sparse);
+        hashlife.top = Some(top);
+        let mut cells = hashlife.live_cells().collect::<Vec<_>>();
+        let mut expected_cells = expected.live_cells().collect::<Vec<_>>();
+        cells.sort_unstable();
+        expected_cells.sort_unstable();
+        assert_eq!(cells, expected_cells);
+        assert_eq!(hashlife.population(), 127);
+
+        let empty = hashlife.construct_from_runs(Runs::default());
+        assert_eq!(hashlife.store.get(empty).population, 0);
+    }
+
+    #[test]
+    fn long_runs_share_nodes() {
+        let mut sparse = Runs::default();
+        sparse.insert_run(3, 5, 1 << 50);
+        sparse.insert_run(0, 6, (1 << 50) + 3);
+        let mut hashlife = Hashlife::new();
+        let top = hashlife.construct_from_runs(sparse);
+        assert_eq!(hashlife.store.get(top).population, (1 << 51) + 3);
+        assert!(hashlife.store.len() < 1000);
+    }
+}
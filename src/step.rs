@@ -0,0 +1,249 @@
+use crate::cache::Cache;
+use crate::rule::Transitions;
+use crate::store::{Nodes, Store, LEAF_LEVEL};
+use crate::{Children, Node, NodeId};
+
+/// Nodes up to this level are advanced directly instead of through their
+/// nonants: their quadrants are leaves.
+const BASE_LEVEL: usize = LEAF_LEVEL + 1;
+
+/// The node table and memo tables the stepping algorithm runs against. The
+/// serial engine borrows them from a `Hashlife`, the parallel engine shares
+/// them between worker threads.
+pub(crate) trait Tables: Nodes {
+    fn join(&mut self, nw: NodeId, ne: NodeId, sw: NodeId, se: NodeId) -> NodeId;
+
+    fn leaf(&mut self, level: usize, bits: u64) -> NodeId;
+
+    /// The quadrants of a node, cut out of its bitboard if it is a leaf.
+    fn split(&mut self, node: NodeId) -> Children;
+
+    /// The exponent `k` that `step` advances by `2^k` generations.
+    fn step_exponent(&self) -> usize;
+
+    /// The base case transitions of the rule every generation is computed with.
+    fn transitions(&self) -> &Transitions;
+
+    fn cached_step(&self, node: NodeId) -> Option<NodeId>;
+
+    fn cache_step(&mut self, node: NodeId, result: NodeId);
+
+    fn cached_jump(&self, node: NodeId) -> Option<NodeId>;
+
+    fn cache_jump(&mut self, node: NodeId, result: NodeId);
+}
+
+/// Tables of a single `Hashlife`, used from one thread.
+pub(crate) struct Serial<'a> {
+    store: &'a mut Store,
+    cache: &'a mut Cache,
+    transitions: &'a Transitions,
+}
+
+impl<'a> Serial<'a> {
+    pub(crate) fn new(store: &'a mut Store, cache: &'a mut Cache, transitions: &'a Transitions) -> Self {
+        Self { store, cache, transitions }
+    }
+}
+
+impl Nodes for Serial<'_> {
+    fn node(&self, id: NodeId) -> Node {
+        *self.store.get(id)
+    }
+}
+
+impl Tables for Serial<'_> {
+    fn join(&mut self, nw: NodeId, ne: NodeId, sw: NodeId, se: NodeId) -> NodeId {
+        self.store.join(Children { nw, ne, sw, se })
+    }
+
+    fn leaf(&mut self, level: usize, bits: u64) -> NodeId {
+        self.store.leaf(level, bits)
+    }
+
+    fn split(&mut self, node: NodeId) -> Children {
+        self.store.split(node)
+    }
+
+    fn step_exponent(&self) -> usize {
+        self.cache.step_exponent
+    }
+
+    fn transitions(&self) -> &Transitions {
+        self.transitions
+    }
+
+    fn cached_step(&self, node: NodeId) -> Option<NodeId> {
+        self.cache.step.get(&self.cache.step_exponent)?.get(&node).copied()
+    }
+
+    fn cache_step(&mut self, node: NodeId, result: NodeId) {
+        self.cache.step.entry(self.cache.step_exponent).or_default().insert(node, result);
+    }
+
+    fn cached_jump(&self, node: NodeId) -> Option<NodeId> {
+        self.cache.jump.get(&node).copied()
+    }
+
+    fn cache_jump(&mut self, node: NodeId, result: NodeId) {
+        self.cache.jump.insert(node, result);
+    }
+}
+
+/// A nonant is a 1/9 separation of a space. This structure represents a node
+/// that is separated into 9 nonants.
+///
+/// Invarient: The node that constructs this nonant collection must have
+/// `level>=5`.
+pub(crate) struct Nonants {
+    pub(crate) nw: NodeId,
+    pub(crate) ne: NodeId,
+    pub(crate) sw: NodeId,
+    pub(crate) se: NodeId,
+    pub(crate) n_: NodeId,
+    pub(crate) e_: NodeId,
+    pub(crate) s_: NodeId,
+    pub(crate) w_: NodeId,
+    pub(crate) c_: NodeId,
+}
+
+/// Separates a node into 9
+pub(crate) fn split_nonants<T: Tables>(tables: &mut T, node: NodeId) -> Nonants {
+    if tables.node(node).level <= BASE_LEVEL {
+        panic!("attempted to bread node into 9x9 at level {}", tables.node(node).level);
+    }
+
+    let c = tables.children(node);
+    let g = tables.grand_children(node);
+
+    Nonants {
+        nw: c.nw,
+        ne: c.ne,
+        sw: c.sw,
+        se: c.se,
+        n_: tables.join(g.nwne, g.nenw, g.nwse, g.nesw),
+        e_: tables.join(g.nesw, g.nese, g.senw, g.sene),
+        s_: tables.join(g.swne, g.senw, g.swse, g.sesw),
+        w_: tables.join(g.nwsw, g.nwse, g.swnw, g.swne),
+        c_: tables.join(g.nwse, g.nesw, g.swne, g.senw),
+    }
+}
+
+pub(crate) fn join_nonants<T: Tables>(tables: &mut T, nodes: Nonants) -> NodeId {
+    let nw = tables.split(nodes.nw);
+    let ne = tables.split(nodes.ne);
+    let sw = tables.split(nodes.sw);
+    let se = tables.split(nodes.se);
+    let n_ = tables.split(nodes.n_);
+    let e_ = tables.split(nodes.e_);
+    let s_ = tables.split(nodes.s_);
+    let w_ = tables.split(nodes.w_);
+    let c_ = tables.split(nodes.c_);
+    let nw_res = tables.join(nw.se, n_.sw, w_.ne, c_.nw);
+    let ne_res = tables.join(n_.se, ne.sw, c_.ne, e_.nw);
+    let sw_res = tables.join(w_.se, c_.sw, sw.ne, s_.nw);
+    let se_res = tables.join(c_.se, e_.sw, s_.ne, se.nw);
+    tables.join(nw_res, ne_res, sw_res, se_res)
+}
+
+/// Advance the centre of a node by `2^k` generations, where `k` is the
+/// exponent the step cache is currently set to. The result is one level
+/// lower than the node.
+///
+/// Invarient: Node.level >= 2 and Node.level >= k + 2
+pub(crate) fn step<T: Tables>(tables: &mut T, node: NodeId) -> NodeId {
+    let level = tables.node(node).level;
+    if level <= tables.step_exponent() + 2 {
+        return jump(tables, node);
+    }
+    if let Some(result) = tables.cached_step(node) {
+        return result;
+    }
+    if level <= BASE_LEVEL {
+        let result = advance_base(tables, node, 1 << tables.step_exponent());
+        tables.cache_step(node, result);
+        return result;
+    }
+    let mut g9x9 = split_nonants(tables, node);
+
+    g9x9.nw = step(tables, g9x9.nw);
+    g9x9.ne = step(tables, g9x9.ne);
+    g9x9.sw = step(tables, g9x9.sw);
+    g9x9.se = step(tables, g9x9.se);
+    g9x9.n_ = step(tables, g9x9.n_);
+    g9x9.e_ = step(tables, g9x9.e_);
+    g9x9.s_ = step(tables, g9x9.s_);
+    g9x9.w_ = step(tables, g9x9.w_);
+    g9x9.c_ = step(tables, g9x9.c_);
+
+    let result = join_nonants(tables, g9x9);
+    tables.cache_step(node, result);
+    result
+}
+
+/// Advance the centre of a node by `2^(level-2)` generations. The nonants
+/// are advanced half of the way, recombined into four overlapping
+/// quadrants and then advanced the rest of the way. The result is one
+/// level lower than the node.
+///
+/// Invarient: Node.level >= 2
+pub(crate) fn jump<T: Tables>(tables: &mut T, node: NodeId) -> NodeId {
+    if let Some(result) = tables.cached_jump(node) {
+        return result;
+    }
+    let result = match tables.node(node).level {
+        0 => panic!("attempted to step a node with level 0"),
+        1 => panic!("attempted to step a node with level 1"),
+        level if level <= BASE_LEVEL => advance_base(tables, node, 1 << (level - 2)),
+        _ => {
+            let g9x9 = split_nonants(tables, node);
+
+            // First half: each nonant is advanced by 2^(level-3).
+            let nw = jump(tables, g9x9.nw);
+            let ne = jump(tables, g9x9.ne);
+            let sw = jump(tables, g9x9.sw);
+            let se = jump(tables, g9x9.se);
+            let n_ = jump(tables, g9x9.n_);
+            let e_ = jump(tables, g9x9.e_);
+            let s_ = jump(tables, g9x9.s_);
+            let w_ = jump(tables, g9x9.w_);
+            let c_ = jump(tables, g9x9.c_);
+
+            // Second half: the overlapping quadrants are advanced by 2^(level-3).
+            let nw_quad = tables.join(nw, n_, w_, c_);
+            let ne_quad = tables.join(n_, ne, c_, e_);
+            let sw_quad = tables.join(w_, c_, sw, s_);
+            let se_quad = tables.join(c_, e_, s_, se);
+            let nw = jump(tables, nw_quad);
+            let ne = jump(tables, ne_quad);
+            let sw = jump(tables, sw_quad);
+            let se = jump(tables, se_quad);
+            tables.join(nw, ne, sw, se)
+        },
+    };
+    tables.cache_jump(node, result);
+    result
+}
+
+/// Advance the centre of a node of at most `BASE_LEVEL` by the given number
+/// of generations, at most `2^(level-2)`. The cells are gathered into rows
+/// of 16 bits that shrink by one cell on each side every generation, the
+/// result is the leaf made of the centre half of the node.
+fn advance_base<T: Tables>(tables: &mut T, node: NodeId, generations: usize) -> NodeId {
+    let level = tables.node(node).level;
+    let side = 1 << level;
+    let mut rows = tables.rows(node);
+
+    let mut size = side;
+    for _ in 0..generations {
+        rows = tables.transitions().generation(&rows, size);
+        size -= 2;
+    }
+
+    let half = side / 2;
+    let offset = (size - half) / 2;
+    let bits = (0..half).fold(0u64, |bits, row| {
+        bits | (u64::from(rows[offset + row] >> offset) & ((1 << half) - 1)) << (row * 8)
+    });
+    tables.leaf(level - 1, bits)
+}
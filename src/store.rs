@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::{Automata, Children, Content, GrandChildren, Node, NodeId};
+
+/// Nodes up to this level are leaves holding their cells in an 8x8 bitboard.
+pub(crate) const LEAF_LEVEL: usize = 3;
+
+/// Trees taller than this would cover coordinates beyond an `isize`.
+pub(crate) const MAX_LEVEL: usize = 62;
+
+/// Arena holding every node of a universe. Nodes are addressed by `NodeId`
+/// and hash-consed through `index`, so a quadrant exists at most once.
+/// Slots released by the garbage collector are reused by later joins.
+pub(crate) struct Store {
+    nodes: Vec<Node>,
+    /// Canonical node table. The single cell leaves are not in it, they are
+    /// always `NodeId::DEAD` and `NodeId::ALIVE`.
+    index: HashMap<Node, NodeId>,
+    free: Vec<NodeId>,
+}
+
+impl Store {
+    pub(crate) fn new() -> Self {
+        Self {
+            nodes: vec![Node::leaf(0, Automata::Dead as u64), Node::leaf(0, Automata::Alive as u64)],
+            index: HashMap::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, id: NodeId) -> &Node {
+        &self.nodes[id.index()]
+    }
+
+    /// Return the canonical copy of a node if it exists.
+    pub(crate) fn find(&self, node: &Node) -> Option<NodeId> {
+        match node.content {
+            Content::Leaf(0) if node.level == 0 => Some(NodeId::DEAD),
+            Content::Leaf(_) if node.level == 0 => Some(NodeId::ALIVE),
+            _ => self.index.get(node).copied(),
+        }
+    }
+
+    /// Return the canonical copy of a node, adding it to the arena if it does
+    /// not exist yet.
+    pub(crate) fn insert(&mut self, node: Node) -> NodeId {
+        if let Some(id) = self.find(&node) {
+            return id;
+        }
+        let id = if let Some(id) = self.free.pop() {
+            self.nodes[id.index()] = node;
+            id
+        } else {
+            let id = u32::try_from(self.nodes.len()).expect("node arena is full");
+            self.nodes.push(node);
+            NodeId(id)
+        };
+        self.index.insert(node, id);
+        id
+    }
+
+    /// Return the canonical node with the given quadrants, creating it if it
+    /// does not exist yet.
+    pub(crate) fn join(&mut self, children: Children) -> NodeId {
+        let node = self.joined(children);
+        self.insert(node)
+    }
+
+    /// Return the canonical leaf with the given cells.
+    pub(crate) fn leaf(&mut self, level: usize, bits: u64) -> NodeId {
+        self.insert(Node::leaf(level, bits))
+    }
+
+    /// The quadrants of a node. The quadrants of a leaf are cut out of its
+    /// bitboard and added to the arena.
+    pub(crate) fn split(&mut self, id: NodeId) -> Children {
+        let node = *self.get(id);
+        match node.content {
+            Content::Branch(children) => children,
+            Content::Leaf(_) => {
+                let [nw, ne, sw, se] = node.quadrants();
+                Children {
+                    nw: self.insert(nw),
+                    ne: self.insert(ne),
+                    sw: self.insert(sw),
+                    se: self.insert(se),
+                }
+            },
+        }
+    }
+
+    /// The node one level lower made of the centre half of a node.
+    pub(crate) fn centre(&mut self, id: NodeId) -> NodeId {
+        let c = self.split(id);
+        let nw = self.split(c.nw);
+        let ne = self.split(c.ne);
+        let sw = self.split(c.sw);
+        let se = self.split(c.se);
+        self.join(Children { nw: nw.se, ne: ne.sw, sw: sw.ne, se: se.nw })
+    }
+
+    /// Add canonical nodes built outside of the arena under the ids they
+    /// were given there, all at or past `capacity`. Slots between them that
+    /// were not given out are released for reuse.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn adopt<I>(&mut self, nodes: I)
+    where
+        I: IntoIterator<Item = (NodeId, Node)>,
+    {
+        let start = self.nodes.len();
+        let mut used = Vec::new();
+        for (id, node) in nodes {
+            let slot = id.index() - start;
+            if slot >= used.len() {
+                used.resize(slot + 1, false);
+                self.nodes.resize(start + slot + 1, Node::leaf(0, 0));
+            }
+            used[slot] = true;
+            self.nodes[id.index()] = node;
+            self.index.insert(node, id);
+        }
+        let unused = used.iter().enumerate().filter(|&(_, &used)| !used);
+        self.free.extend(unused.map(|(slot, _)| NodeId((start + slot) as u32)));
+    }
+
+    /// Number of canonical nodes.
+    pub(crate) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Number of slots in the arena, every `NodeId` indexes below it.
+    pub(crate) fn capacity(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Release every node that is not marked, returning how many were freed.
+    pub(crate) fn sweep(&mut self, marked: &[bool]) -> usize {
+        let free = &mut self.free;
+        let before = self.index.len();
+        self.index.retain(|_, id| {
+            if marked[id.index()] {
+                true
+            } else {
+                free.push(*id);
+                false
+            }
+        });
+        before - self.index.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn as_array(&self, id: NodeId) -> Vec<Vec<Automata>> {
+        let node = self.get(id);
+        if let Content::Leaf(bits) = node.content {
+            let side = 1 << node.level;
+            return (0..side)
+                .map(|row| (0..side).map(|col| Automata::from((bits >> (row * 8 + col) & 1) as usize)).collect())
+                .collect();
+        }
+        let children = self.children(id);
+        let nw = self.as_array(children.nw);
+        let ne = self.as_array(children.ne);
+        let sw = self.as_array(children.sw);
+        let se = self.as_array(children.se);
+        let top = nw.into_iter()
+            .zip(ne)
+            .map(|(left, right)| {
+                let mut result = Vec::with_capacity(left.len() + right.len());
+                result.extend(left);
+                result.extend(right);
+                result
+            })
+            .collect::<Vec<Vec<Automata>>>();
+        let bottom = sw.into_iter()
+            .zip(se)
+            .map(|(left, right)| {
+                let mut result = Vec::with_capacity(left.len() + right.len());
+                result.extend(left);
+                result.extend(right);
+                result
+            })
+            .collect::<Vec<Vec<Automata>>>();
+        let mut rows = Vec::with_capacity(top.len() + bottom.len());
+        rows.extend(top);
+        rows.extend(bottom);
+        rows
+    }
+}
+
+/// Read access to nodes. Implemented by the arena itself and by the tables
+/// the stepping algorithm runs against.
+pub(crate) trait Nodes {
+    fn node(&self, id: NodeId) -> Node;
+
+    /// The node with the given quadrants. Quadrants below the leaf level are
+    /// merged into a single leaf.
+    fn joined(&self, children: Children) -> Node {
+        let nw = self.node(children.nw);
+        let ne = self.node(children.ne);
+        let sw = self.node(children.sw);
+        let se = self.node(children.se);
+        assert_eq!(nw.level, ne.level);
+        assert_eq!(nw.level, sw.level);
+        assert_eq!(nw.level, se.level);
+        if nw.level < LEAF_LEVEL {
+            let side = 1 << nw.level;
+            let bits = place(nw.bits(), side, 0, 0)
+                | place(ne.bits(), side, side, 0)
+                | place(sw.bits(), side, 0, side)
+                | place(se.bits(), side, side, side);
+            return Node::leaf(nw.level + 1, bits);
+        }
+        Node {
+            level: nw.level + 1,
+            population: nw.population + ne.population + sw.population + se.population,
+            content: Content::Branch(children),
+        }
+    }
+
+    fn children(&self, id: NodeId) -> Children {
+        match self.node(id).content {
+            Content::Branch(children) => children,
+            Content::Leaf(_) => panic!("unable to get the children of a leaf"),
+        }
+    }
+
+    fn grand_children(&self, id: NodeId) -> GrandChildren {
+        let c = self.children(id);
+        let nw = self.children(c.nw);
+        let ne = self.children(c.ne);
+        let sw = self.children(c.sw);
+        let se = self.children(c.se);
+        GrandChildren {
+            nwne: nw.ne,
+            nwsw: nw.sw,
+            nwse: nw.se,
+            nenw: ne.nw,
+            nesw: ne.sw,
+            nese: ne.se,
+            swnw: sw.nw,
+            swne: sw.ne,
+            swse: sw.se,
+            senw: se.nw,
+            sene: se.ne,
+            sesw: se.sw,
+        }
+    }
+
+    /// The cells of a node up to one level above the leaves, one row per
+    /// `u16` from the top, where bit `c` of a row is the cell on column `c`.
+    fn rows(&self, id: NodeId) -> [u16; 16] {
+        let n = self.node(id);
+        assert!(n.level <= LEAF_LEVEL + 1, "a level {} node does not fit in 16 rows", n.level);
+        let mut rows = [0u16; 16];
+        let mut gather = |bits: u64, x: usize, y: usize| {
+            for row in 0..(1 << n.level).min(8) {
+                rows[y + row] |= ((bits >> (row * 8)) as u16 & 0xff) << x;
+            }
+        };
+        match n.content {
+            Content::Leaf(bits) => gather(bits, 0, 0),
+            Content::Branch(c) => {
+                gather(self.node(c.nw).bits(), 0, 0);
+                gather(self.node(c.ne).bits(), 8, 0);
+                gather(self.node(c.sw).bits(), 0, 8);
+                gather(self.node(c.se).bits(), 8, 8);
+            },
+        }
+        rows
+    }
+}
+
+impl Nodes for Store {
+    fn node(&self, id: NodeId) -> Node {
+        *self.get(id)
+    }
+}
+
+impl Node {
+    /// A leaf of the given level. Its cells are the top left `2^level` square
+    /// of the bitboard, row by row from the top with eight bits per row.
+    pub(crate) fn leaf(level: usize, bits: u64) -> Self {
+        assert!(level <= LEAF_LEVEL);
+        Node {
+            level,
+            population: u128::from(bits.count_ones()),
+            content: Content::Leaf(bits),
+        }
+    }
+
+    /// The bitboard of a leaf.
+    pub(crate) fn bits(&self) -> u64 {
+        match self.content {
+            Content::Leaf(bits) => bits,
+            Content::Branch(_) => panic!("a branch has no bitboard"),
+        }
+    }
+
+    /// The four quadrant leaves of a leaf.
+    pub(crate) fn quadrants(&self) -> [Node; 4] {
+        assert!(self.level > 0, "unable to split a single cell");
+        let bits = self.bits();
+        let half = 1 << (self.level - 1);
+        let quadrant = |x, y| Node::leaf(self.level - 1, cut(bits, half, x, y));
+        [quadrant(0, 0), quadrant(half, 0), quadrant(0, half), quadrant(half, half)]
+    }
+}
+
+fn row_mask(side: usize) -> u64 {
+    (1 << side) - 1
+}
+
+/// The bitboard of a `side` wide square of living cells.
+pub(crate) fn square(side: usize) -> u64 {
+    (0..side).fold(0, |square, row| square | row_mask(side) << (row * 8))
+}
+
+/// Move the `side` wide square of a bitboard at column `x` and row `y` to the
+/// top left.
+pub(crate) fn cut(bits: u64, side: usize, x: usize, y: usize) -> u64 {
+    (0..side).fold(0, |square, row| square | (bits >> ((y + row) * 8 + x) & row_mask(side)) << (row * 8))
+}
+
+/// Move the `side` wide square at the top left of a bitboard to column `x`
+/// and row `y`.
+fn place(bits: u64, side: usize, x: usize, y: usize) -> u64 {
+    (0..side).fold(0, |board, row| board | (bits >> (row * 8) & row_mask(side)) << ((y + row) * 8 + x))
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use crate::pattern::Memo;
+use crate::{top_corner, BoundingBox, Content, Edge, Hashlife, NodeId};
+
+/// The symmetries of the square other than the identity. Rotations are
+/// clockwise and the transposes reflect across the diagonals of the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirror left and right.
+    FlipHorizontal,
+    /// Mirror top and bottom.
+    FlipVertical,
+    /// Reflect across the diagonal from the top left to the bottom right.
+    Transpose,
+    /// Reflect across the diagonal from the bottom left to the top right.
+    AntiTranspose,
+}
+
+impl Transform {
+    /// The matrix `(a, b, c, d)` mapping `x`, `y` to `ax + by`, `cx + dy`.
+    fn matrix(self) -> (isize, isize, isize, isize) {
+        match self {
+            Transform::Rotate90 => (0, 1, -1, 0),
+            Transform::Rotate180 => (-1, 0, 0, -1),
+            Transform::Rotate270 => (0, -1, 1, 0),
+            Transform::FlipHorizontal => (-1, 0, 0, 1),
+            Transform::FlipVertical => (1, 0, 0, -1),
+            Transform::Transpose => (0, -1, -1, 0),
+            Transform::AntiTranspose => (0, 1, 1, 0),
+        }
+    }
+
+    /// Where the cell at `x`, `y` goes, the origin stays in place.
+    pub fn apply(self, x: isize, y: isize) -> (isize, isize) {
+        let (a, b, c, d) = self.matrix();
+        (a * x + b * y, c * x + d * y)
+    }
+
+    /// Where the cell on column `col` and row `row` of a square of the given
+    /// side goes when the square is turned about its centre.
+    fn apply_square(self, col: usize, row: usize, side: usize) -> (usize, usize) {
+        // Doubled coordinates keep the centre of an even square on a cell.
+        let last = side as isize - 1;
+        let (u, v) = self.apply(2 * col as isize - last, last - 2 * row as isize);
+        (((u + last) / 2) as usize, ((last - v) / 2) as usize)
+    }
+}
+
+impl Hashlife {
+    /// Apply a symmetry of the square to every cell, about the origin. A
+    /// torus wraps the cells around and a truncated universe drops the cells
+    /// leaving it.
+    pub fn transform(&mut self, transform: Transform) {
+        let top = match self.top {
+            Some(top) if self.store.get(top).level > 0 => top,
+            _ => return,
+        };
+        self.top = Some(self.transform_node(top, transform, &mut HashMap::new()));
+        // The top node turns about its centre, which is half a cell below and
+        // left of the origin.
+        let (a, b, c, d) = transform.matrix();
+        self.translate((a + b < 0) as isize, (c + d < 0) as isize);
+    }
+
+    fn transform_node(&mut self, node: NodeId, transform: Transform, transformed: &mut HashMap<NodeId, NodeId>) -> NodeId {
+        if let Some(&id) = transformed.get(&node) {
+            return id;
+        }
+        let n = *self.store.get(node);
+        let result = match n.content {
+            Content::Leaf(bits) => {
+                let side = 1 << n.level;
+                let mut result = 0;
+                for row in 0..side {
+                    for col in 0..side {
+                        let (c, r) = transform.apply_square(col, row, side);
+                        result |= (bits >> (row * 8 + col) & 1) << (r * 8 + c);
+                    }
+                }
+                self.store.leaf(n.level, result)
+            },
+            Content::Branch(c) => {
+                let mut quadrants = [NodeId::DEAD; 4];
+                for (i, &child) in [c.nw, c.ne, c.sw, c.se].iter().enumerate() {
+                    let (col, row) = transform.apply_square(i % 2, i / 2, 2);
+                    quadrants[row * 2 + col] = self.transform_node(child, transform, transformed);
+                }
+                let [nw, ne, sw, se] = quadrants;
+                self.join(nw, ne, sw, se)
+            },
+        };
+        transformed.insert(node, result);
+        result
+    }
+
+    /// Move every cell by `dx` columns to the right and `dy` rows up. An
+    /// infinite universe grows to follow the cells, a torus wraps them around
+    /// and a truncated universe drops the cells leaving it.
+    pub fn translate(&mut self, dx: isize, dy: isize) {
+        let top = match self.top {
+            Some(top) => top,
+            None => return,
+        };
+        let mut memo = Memo::default();
+        let level = self.store.get(top).level;
+        let side = 1isize << level;
+        let (left, top_y) = top_corner(level);
+        let moved = match self.edge {
+            Edge::Infinite => {
+                let area = BoundingBox::from(top_y + dy, top_y + dy - side + 1, left + dx, left + dx + side - 1);
+                let mut level = level;
+                while !BoundingBox::square(level).encloses(&area) {
+                    level += 1;
+                }
+                let mut moved = self.place(top, left + dx, top_y + dy, level, &mut memo);
+                // Drop the empty border so repeated moves do not keep growing
+                // the tree.
+                while self.store.get(moved).level >= 2 {
+                    let centre = self.store.centre(moved);
+                    if self.store.get(centre).population != self.store.get(moved).population {
+                        break;
+                    }
+                    moved = centre;
+                }
+                moved
+            },
+            Edge::Torus => {
+                // The tree tiled twice over holds every wrapped position.
+                let tiled = self.join(top, top, top, top);
+                let (ox, oy) = ((-dx).rem_euclid(side) as usize, dy.rem_euclid(side) as usize);
+                self.window(tiled, ox, oy, level, &mut memo)
+            },
+            Edge::Truncate => self.place(top, left + dx, top_y + dy, level, &mut memo),
+        };
+        self.top = Some(moved);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Automata, CombineMode, Pattern};
+
+    const TRANSFORMS: [Transform; 7] = [
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::FlipHorizontal,
+        Transform::FlipVertical,
+        Transform::Transpose,
+        Transform::AntiTranspose,
+    ];
+
+    fn asymmetric() -> Vec<u8> {
+        vec![
+            1,1,0,0,1,
+            0,1,1,0,0,
+            0,1,0,0,1,
+            1,0,0,1,1,
+        ]
+    }
+
+    fn alive(hashlife: &Hashlife, x: isize, y: isize) -> bool {
+        let level = hashlife.max_level();
+        BoundingBox::square(level).contains(x, y) && hashlife.get(x, y) == Some(Automata::Alive)
+    }
+
+    #[test]
+    fn transforms_move_cells_about_the_origin() {
+        let original = Hashlife::from_array(asymmetric(), 5, 4, Edge::Infinite);
+        for &transform in &TRANSFORMS {
+            let mut hashlife = Hashlife::from_array(asymmetric(), 5, 4, Edge::Infinite);
+            hashlife.transform(transform);
+            for x in -8..8 {
+                for y in -8..8 {
+                    let (tx, ty) = transform.apply(x, y);
+                    assert_eq!(alive(&hashlife, tx, ty), alive(&original, x, y), "{:?} at ({}, {})", transform, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transforms_compose() {
+        let mut hashlife = Hashlife::from_array(asymmetric(), 5, 4, Edge::Torus);
+        let top = hashlife.top;
+        hashlife.transform(Transform::Rotate90);
+        hashlife.transform(Transform::Rotate180);
+        hashlife.transform(Transform::Rotate90);
+        assert_eq!(hashlife.top, top);
+        hashlife.transform(Transform::Transpose);
+        hashlife.transform(Transform::Rotate90);
+        hashlife.transform(Transform::FlipHorizontal);
+        assert_eq!(hashlife.top, top);
+    }
+
+    #[test]
+    fn translate_follows_edges() {
+        let original = Hashlife::from_array(asymmetric(), 5, 4, Edge::Infinite);
+        for &edge in &[Edge::Infinite, Edge::Torus, Edge::Truncate] {
+            let mut hashlife = Hashlife::from_array(asymmetric(), 5, 4, edge);
+            hashlife.translate(3, -2);
+            for x in -8..8 {
+                for y in -8..8 {
+                    let expected = match edge {
+                        Edge::Infinite => alive(&original, x - 3, y + 2),
+                        // The universe is the 8x8 square around the origin.
+                        Edge::Torus => {
+                            let wrap = |v: isize| (v + 4).rem_euclid(8) - 4;
+                            BoundingBox::square(3).contains(x, y) && alive(&original, wrap(x - 3), wrap(y + 2))
+                        },
+                        Edge::Truncate => BoundingBox::square(3).contains(x, y) && alive(&original, x - 3, y + 2),
+                    };
+                    assert_eq!(alive(&hashlife, x, y), expected, "{:?} at ({}, {})", edge, x, y);
+                }
+            }
+        }
+
+        // Moving far away and back leaves a compact tree.
+        let mut hashlife = Hashlife::from_array(asymmetric(), 5, 4, Edge::Infinite);
+        hashlife.translate(1_000_000, 0);
+        assert!(alive(&hashlife, 1_000_002, 1));
+        hashlife.translate(-1_000_000, 0);
+        assert_eq!(hashlife.max_level(), original.max_level());
+    }
+
+    #[test]
+    fn transformed_pattern_pastes_in_place() {
+        let mut pattern = Pattern::from_array(asymmetric(), 5, 4);
+        pattern.transform(Transform::Rotate90);
+        pattern.translate(10, 0);
+        assert_eq!((pattern.width(), pattern.height()), (4, 5));
+        let bounds = *pattern.bounds();
+        let mut hashlife = Hashlife::from_array(vec![0; 4], 2, 2, Edge::Infinite);
+        hashlife.paste(&pattern, bounds.left(), bounds.top(), CombineMode::Copy);
+
+        let original = Hashlife::from_array(asymmetric(), 5, 4, Edge::Infinite);
+        for x in -8..8 {
+            for y in -8..8 {
+                let (tx, ty) = Transform::Rotate90.apply(x, y);
+                assert_eq!(alive(&hashlife, tx + 10, ty), alive(&original, x, y), "({}, {})", x, y);
+            }
+        }
+    }
+}